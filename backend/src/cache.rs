@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use shipyard::{CacheCounts, CacheStats, Tags};
+
+use crate::{registry::Registry, req_manifest, req_resolve, req_tags, store::Store};
+
+/// hits and misses of a cache since startup
+struct Counter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Counter {
+    const fn new() -> Counter {
+        Counter {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counts(&self) -> CacheCounts {
+        CacheCounts {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+static TAGS: Counter = Counter::new();
+static MANIFESTS: Counter = Counter::new();
+
+pub fn stats() -> CacheStats {
+    CacheStats {
+        tags: TAGS.counts(),
+        manifests: MANIFESTS.counts(),
+    }
+}
+
+/// tags from the store when cached, from the registry otherwise, cached for `ttl` seconds
+pub async fn tags(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+    ttl: usize,
+) -> Result<Tags, anyhow::Error> {
+    if let Ok(Some(tags)) = store.tags(registry.name(), image).await {
+        TAGS.hit();
+        return Ok(Tags {
+            name: image.to_string(),
+            tags,
+        });
+    }
+    TAGS.miss();
+    let tags = req_tags(registry, image).await?;
+    if let Err(e) = store
+        .set_tags(registry.name(), image, &tags.tags, ttl)
+        .await
+    {
+        eprintln!("Failed to cache tags of {}: {}", image, e);
+    }
+    Ok(tags)
+}
+
+pub async fn invalidate_tags(store: &dyn Store, registry: &Registry, image: &str) {
+    if let Err(e) = store.invalidate_tags(registry.name(), image).await {
+        eprintln!("Failed to invalidate tags of {}: {}", image, e);
+    }
+}
+
+/// drops the manifest cached under a digest deleted from the registry
+pub async fn invalidate_manifest(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+    digest: &str,
+) {
+    if let Err(e) = store
+        .invalidate_manifest(registry.name(), image, digest)
+        .await
+    {
+        eprintln!("Failed to invalidate manifest {}@{}: {}", image, digest, e);
+    }
+}
+
+/// manifest of an image by tag or digest, cached by digest for `ttl` seconds
+///
+/// the content behind a digest never changes, so a tag only costs a `HEAD` request when
+/// the manifest it points to is cached, and a moved tag is never served stale
+pub async fn manifest(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+    reference: &str,
+    ttl: usize,
+) -> Result<String, anyhow::Error> {
+    Ok(digest_manifest(store, registry, image, reference, ttl)
+        .await?
+        .1)
+}
+
+/// digest and manifest of an image by tag or digest, see `manifest`
+pub async fn digest_manifest(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+    reference: &str,
+    ttl: usize,
+) -> Result<(String, String), anyhow::Error> {
+    // tags cannot contain ':', digests always do
+    let (digest, fetched) = match reference.contains(':') {
+        true => (reference.to_string(), None),
+        false => req_resolve(registry, image, reference).await?,
+    };
+    if let Ok(Some(manifest)) = store.manifest(registry.name(), image, &digest).await {
+        MANIFESTS.hit();
+        return Ok((digest, manifest));
+    }
+    MANIFESTS.miss();
+    let manifest = match fetched {
+        Some(manifest) => manifest,
+        None => req_manifest(registry, image, &digest).await?,
+    };
+    if let Err(e) = store
+        .set_manifest(registry.name(), image, &digest, &manifest, ttl)
+        .await
+    {
+        eprintln!("Failed to cache manifest {}@{}: {}", image, digest, e);
+    }
+    Ok((digest, manifest))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::{config::RegistryConfig, store::MemoryStore};
+
+    const MANIFEST: &str = r#"{"schemaVersion":2}"#;
+
+    /// a registry behind a proxy that dropped both the digest header and the ETag
+    async fn manifest_without_digest() -> HttpResponse {
+        HttpResponse::Ok().body(MANIFEST)
+    }
+
+    #[actix_rt::test]
+    async fn digests_manifests_without_a_digest_header() {
+        let srv = test::start(|| {
+            App::new().route(
+                "/v2/app/manifests/latest",
+                web::route().to(manifest_without_digest),
+            )
+        });
+        let registry = Registry::new(&RegistryConfig {
+            name: "hub".to_string(),
+            url: srv.url("/v2"),
+            username: None,
+            password: None,
+            timeout_secs: 5,
+        });
+        let store = MemoryStore::open(None).unwrap();
+        let (digest, manifest) = digest_manifest(&store, &registry, "app", "latest", 60)
+            .await
+            .unwrap();
+        assert_eq!(
+            digest,
+            "sha256:bafebd36189ad3688b7b3915ea55d461e0bfcfbdde11e54b0a123999fb6be50f"
+        );
+        assert_eq!(manifest, MANIFEST);
+        assert_eq!(
+            store.manifest("hub", "app", &digest).await.unwrap(),
+            Some(MANIFEST.to_string())
+        );
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use shipyard::{
+    ComparedImage, ConfigChange, ContainerConfig, DockerManifest, ImageConfig, ImageDiff, StepDiff,
+};
+
+use crate::{cache, error::Error, registry::Registry, req_config, store::Store};
+
+/// one side of a comparison, resolved to a single image
+struct Side {
+    summary: ComparedImage,
+    manifest: DockerManifest,
+    config: Option<ImageConfig>,
+}
+
+/// resolves `image:reference`, down to `platform` when it is a manifest list
+///
+/// without `required`, a list lacking `platform` falls back to its first runnable platform
+async fn resolve(
+    store: &dyn Store,
+    registry: &Registry,
+    (image, reference): (&str, &str),
+    platform: Option<&str>,
+    required: bool,
+    ttl: usize,
+) -> Result<Side, anyhow::Error> {
+    let (digest, manifest) = cache::digest_manifest(store, registry, image, reference, ttl).await?;
+    let mut manifest = shipyard::get_manifest(&manifest)?;
+    let mut summary = ComparedImage {
+        image: image.to_string(),
+        reference: reference.to_string(),
+        digest,
+        ..Default::default()
+    };
+    if let Some(platforms) = manifest.platforms() {
+        // attestations and other artifacts are listed with an unknown platform
+        let runnable: Vec<(String, String)> = platforms
+            .iter()
+            .filter_map(|p| match &p.platform {
+                Some(platform) if platform.os != "unknown" => {
+                    Some((platform.to_string(), p.digest.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        summary.platforms = runnable.iter().map(|(name, _)| name.clone()).collect();
+        let found = runnable
+            .iter()
+            .find(|(name, _)| Some(name.as_str()) == platform);
+        let (name, digest) = match (found, required) {
+            (Some(found), _) => found.clone(),
+            (None, false) if !runnable.is_empty() => runnable[0].clone(),
+            _ => {
+                return Err(Error::not_found(format!(
+                    "{}:{} has no platform {}",
+                    image,
+                    reference,
+                    platform.unwrap_or("to run")
+                ))
+                .into())
+            }
+        };
+        manifest =
+            shipyard::get_manifest(&cache::manifest(store, registry, image, &digest, ttl).await?)?;
+        summary.platform = Some(name);
+        summary.digest = digest;
+    }
+    summary.size = manifest.size();
+    // schema 1 manifests have no config blob
+    let config = match manifest.config_digest() {
+        Some(_) => Some(req_config(registry, image, &manifest).await?),
+        None => None,
+    };
+    summary.created = config.as_ref().and_then(|c| c.created.clone());
+    Ok(Side {
+        summary,
+        manifest,
+        config,
+    })
+}
+
+/// changes from `base` to `target`, both `(image, reference)`
+///
+/// manifest lists are compared on `platform`, or on the first runnable platform of the base
+pub async fn compare(
+    store: &dyn Store,
+    registry: &Registry,
+    base: (&str, &str),
+    target: (&str, &str),
+    platform: Option<&str>,
+    ttl: usize,
+) -> Result<ImageDiff, anyhow::Error> {
+    let base = resolve(store, registry, base, platform, platform.is_some(), ttl).await?;
+    // the target is compared on the platform of the base when it has it
+    let preferred = platform.or(base.summary.platform.as_deref());
+    let target = resolve(store, registry, target, preferred, platform.is_some(), ttl).await?;
+
+    let base_digests: HashSet<&str> = base
+        .manifest
+        .layers()
+        .iter()
+        .map(|l| l.digest.as_str())
+        .collect();
+    let target_digests: HashSet<&str> = target
+        .manifest
+        .layers()
+        .iter()
+        .map(|l| l.digest.as_str())
+        .collect();
+    let (shared_layers, base_layers) = base
+        .manifest
+        .layers()
+        .iter()
+        .cloned()
+        .partition(|l| target_digests.contains(l.digest.as_str()));
+    let target_layers = target
+        .manifest
+        .layers()
+        .iter()
+        .filter(|l| !base_digests.contains(l.digest.as_str()))
+        .cloned()
+        .collect();
+
+    let size_delta = match (base.summary.size, target.summary.size) {
+        (Some(base), Some(target)) => Some(target as i64 - base as i64),
+        _ => None,
+    };
+    let base_config = base.config.as_ref().and_then(|c| c.config.as_ref());
+    let target_config = target.config.as_ref().and_then(|c| c.config.as_ref());
+    let history = align(&steps(&base)?, &steps(&target)?);
+    Ok(ImageDiff {
+        shared_layers,
+        base_layers,
+        target_layers,
+        size_delta,
+        config: config_changes(base_config, target_config),
+        history,
+        base_platforms: missing(&base.summary.platforms, &target.summary.platforms),
+        target_platforms: missing(&target.summary.platforms, &base.summary.platforms),
+        base: base.summary,
+        target: target.summary,
+    })
+}
+
+/// entries of `from` absent from `other`
+fn missing(from: &[String], other: &[String]) -> Vec<String> {
+    from.iter()
+        .filter(|p| !other.contains(p))
+        .cloned()
+        .collect()
+}
+
+fn config_changes(
+    base: Option<&ContainerConfig>,
+    target: Option<&ContainerConfig>,
+) -> Vec<ConfigChange> {
+    let empty = ContainerConfig::default();
+    let (base, target) = (base.unwrap_or(&empty), target.unwrap_or(&empty));
+    let mut changes = vec![];
+    keyed(&mut changes, "env", env(base), env(target));
+    single(
+        &mut changes,
+        "entrypoint",
+        base.entrypoint.as_ref().map(|e| e.join(" ")),
+        target.entrypoint.as_ref().map(|e| e.join(" ")),
+    );
+    single(
+        &mut changes,
+        "cmd",
+        base.cmd.as_ref().map(|c| c.join(" ")),
+        target.cmd.as_ref().map(|c| c.join(" ")),
+    );
+    keyed(&mut changes, "labels", labels(base), labels(target));
+    keyed(&mut changes, "exposed_ports", ports(base), ports(target));
+    // an empty user is the default one
+    single(
+        &mut changes,
+        "user",
+        base.user.clone().filter(|u| !u.is_empty()),
+        target.user.clone().filter(|u| !u.is_empty()),
+    );
+    changes
+}
+
+fn env(config: &ContainerConfig) -> BTreeMap<String, String> {
+    config
+        .env
+        .iter()
+        .flatten()
+        .map(|var| match var.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (var.clone(), String::new()),
+        })
+        .collect()
+}
+
+fn labels(config: &ContainerConfig) -> BTreeMap<String, String> {
+    config
+        .labels
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+fn ports(config: &ContainerConfig) -> BTreeMap<String, String> {
+    config
+        .exposed_ports
+        .iter()
+        .flat_map(|ports| ports.keys())
+        .map(|port| (port.clone(), port.clone()))
+        .collect()
+}
+
+fn single(
+    changes: &mut Vec<ConfigChange>,
+    field: &str,
+    base: Option<String>,
+    target: Option<String>,
+) {
+    if base != target {
+        changes.push(ConfigChange {
+            field: field.to_string(),
+            key: None,
+            base,
+            target,
+        });
+    }
+}
+
+fn keyed(
+    changes: &mut Vec<ConfigChange>,
+    field: &str,
+    mut base: BTreeMap<String, String>,
+    mut target: BTreeMap<String, String>,
+) {
+    let keys: BTreeSet<String> = base.keys().chain(target.keys()).cloned().collect();
+    for key in keys {
+        let (base, target) = (base.remove(&key), target.remove(&key));
+        if base != target {
+            changes.push(ConfigChange {
+                field: field.to_string(),
+                key: Some(key),
+                base,
+                target,
+            });
+        }
+    }
+}
+
+/// dockerfile instructions of the build steps
+fn steps(side: &Side) -> Result<Vec<String>, anyhow::Error> {
+    let steps = side.manifest.build_steps(side.config.as_ref())?;
+    Ok(steps.iter().map(|step| step.instruction()).collect())
+}
+
+/// pairs the steps of both histories along their longest common subsequence
+fn align(base: &[String], target: &[String]) -> Vec<StepDiff> {
+    // common[i][j] is the longest common subsequence of base[i..] and target[j..]
+    let mut common = vec![vec![0usize; target.len() + 1]; base.len() + 1];
+    for i in (0..base.len()).rev() {
+        for j in (0..target.len()).rev() {
+            common[i][j] = match base[i] == target[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j, mut steps) = (0, 0, vec![]);
+    while i < base.len() || j < target.len() {
+        let step = if i < base.len() && j < target.len() && base[i] == target[j] {
+            i += 1;
+            j += 1;
+            StepDiff {
+                base: Some(base[i - 1].clone()),
+                target: Some(target[j - 1].clone()),
+            }
+        } else if j == target.len() || (i < base.len() && common[i + 1][j] >= common[i][j + 1]) {
+            i += 1;
+            StepDiff {
+                base: Some(base[i - 1].clone()),
+                target: None,
+            }
+        } else {
+            j += 1;
+            StepDiff {
+                base: None,
+                target: Some(target[j - 1].clone()),
+            }
+        };
+        steps.push(step);
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// step as `(base, target)`
+    type Step = (Option<String>, Option<String>);
+    /// change as `(field, key, base, target)`
+    type Change = (String, Option<String>, Option<String>, Option<String>);
+
+    fn steps(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn aligned(base: &[&str], target: &[&str]) -> Vec<Step> {
+        align(&steps(base), &steps(target))
+            .into_iter()
+            .map(|step| (step.base, step.target))
+            .collect()
+    }
+
+    fn both(step: &str) -> Step {
+        (Some(step.to_string()), Some(step.to_string()))
+    }
+
+    fn base(step: &str) -> Step {
+        (Some(step.to_string()), None)
+    }
+
+    fn target(step: &str) -> Step {
+        (None, Some(step.to_string()))
+    }
+
+    fn changes(base: &ContainerConfig, target: &ContainerConfig) -> Vec<Change> {
+        config_changes(Some(base), Some(target))
+            .into_iter()
+            .map(|c| (c.field, c.key, c.base, c.target))
+            .collect()
+    }
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn aligns_identical_histories() {
+        assert_eq!(
+            aligned(&["FROM a", "RUN b"], &["FROM a", "RUN b"]),
+            vec![both("FROM a"), both("RUN b")]
+        );
+    }
+
+    #[test]
+    fn aligns_an_inserted_step() {
+        assert_eq!(
+            aligned(&["FROM a", "CMD c"], &["FROM a", "RUN b", "CMD c"]),
+            vec![both("FROM a"), target("RUN b"), both("CMD c")]
+        );
+        assert_eq!(
+            aligned(&["FROM a", "RUN b", "CMD c"], &["FROM a", "CMD c"]),
+            vec![both("FROM a"), base("RUN b"), both("CMD c")]
+        );
+    }
+
+    #[test]
+    fn aligns_a_replaced_step() {
+        assert_eq!(
+            aligned(&["FROM a", "RUN b", "CMD c"], &["FROM a", "RUN x", "CMD c"]),
+            vec![
+                both("FROM a"),
+                base("RUN b"),
+                target("RUN x"),
+                both("CMD c")
+            ]
+        );
+    }
+
+    #[test]
+    fn aligns_empty_sides() {
+        assert_eq!(aligned(&[], &[]), vec![]);
+        assert_eq!(
+            aligned(&[], &["FROM a", "RUN b"]),
+            vec![target("FROM a"), target("RUN b")]
+        );
+        assert_eq!(aligned(&["FROM a"], &[]), vec![base("FROM a")]);
+    }
+
+    #[test]
+    fn compares_identical_configs() {
+        let config = ContainerConfig {
+            env: Some(steps(&["PATH=/bin"])),
+            cmd: Some(steps(&["sh"])),
+            ..Default::default()
+        };
+        assert_eq!(changes(&config, &config), vec![]);
+        assert!(config_changes(None, None).is_empty());
+    }
+
+    #[test]
+    fn compares_env_by_variable() {
+        let base_config = ContainerConfig {
+            env: Some(steps(&["PATH=/bin", "DEBUG", "EMPTY="])),
+            ..Default::default()
+        };
+        let target_config = ContainerConfig {
+            env: Some(steps(&[
+                "PATH=/usr/bin:/bin",
+                "DEBUG=1",
+                "EMPTY",
+                "NEW=a=b",
+            ])),
+            ..Default::default()
+        };
+        let env = |key: &str, base, target| ("env".to_string(), some(key), base, target);
+        assert_eq!(
+            changes(&base_config, &target_config),
+            vec![
+                env("DEBUG", some(""), some("1")),
+                env("NEW", None, some("a=b")),
+                env("PATH", some("/bin"), some("/usr/bin:/bin")),
+            ]
+        );
+    }
+
+    #[test]
+    fn treats_an_empty_user_as_the_default() {
+        let empty = ContainerConfig {
+            user: some(""),
+            ..Default::default()
+        };
+        let root = ContainerConfig {
+            user: some("root"),
+            ..Default::default()
+        };
+        assert_eq!(changes(&empty, &ContainerConfig::default()), vec![]);
+        assert_eq!(
+            changes(&root, &empty),
+            vec![("user".to_string(), None, some("root"), None)]
+        );
+    }
+
+    #[test]
+    fn compares_single_and_keyed_fields() {
+        let base_config = ContainerConfig {
+            entrypoint: Some(steps(&["/entrypoint.sh"])),
+            labels: Some([("version".to_string(), "1".to_string())].into()),
+            ..Default::default()
+        };
+        let target_config = ContainerConfig {
+            cmd: Some(steps(&["serve", "--port", "80"])),
+            exposed_ports: Some([("80/tcp".to_string(), serde_json::json!({}))].into()),
+            labels: Some([("version".to_string(), "2".to_string())].into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            changes(&base_config, &target_config),
+            vec![
+                ("entrypoint".to_string(), None, some("/entrypoint.sh"), None),
+                ("cmd".to_string(), None, None, some("serve --port 80")),
+                ("labels".to_string(), some("version"), some("1"), some("2")),
+                (
+                    "exposed_ports".to_string(),
+                    some("80/tcp"),
+                    None,
+                    some("80/tcp")
+                ),
+            ]
+        );
+    }
+}
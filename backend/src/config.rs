@@ -0,0 +1,431 @@
+use std::{
+    env, fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use serde::Deserialize;
+
+/// backend configuration, read from the toml file at `SHIPYARD_CONFIG` then overridden
+/// by the `SHIPYARD_*` environment variables
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: ListenConfig,
+    pub redis: RedisConfig,
+    pub storage: StorageConfig,
+    pub registries: Vec<RegistryConfig>,
+    pub catalog: CatalogConfig,
+    pub cache: CacheConfig,
+    pub cors: CorsConfig,
+    pub retention: RetentionConfig,
+    pub events: EventsConfig,
+    pub frontend: FrontendConfig,
+}
+
+/// address the api listens on, only read at startup
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for ListenConfig {
+    fn default() -> ListenConfig {
+        ListenConfig {
+            address: "127.0.0.1".to_string(),
+            port: 8081,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: String,
+}
+
+impl Default for RedisConfig {
+    fn default() -> RedisConfig {
+        RedisConfig {
+            url: "redis://127.0.0.1:6379/".to_string(),
+        }
+    }
+}
+
+/// where catalogs, caches and activity feeds are kept
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// file the memory backend is saved to and restored from, nothing is kept when unset
+    pub snapshot: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Redis,
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StorageBackend, String> {
+        match s {
+            "redis" => Ok(StorageBackend::Redis),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => Err("expected redis or memory".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    /// used in api paths and to namespace stored data
+    pub name: String,
+    /// base url of the registry api, ending with `/v2`
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// seconds before a request to the registry or its token server is abandoned
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogConfig {
+    /// images per page when the request does not set one
+    pub page_size: usize,
+    /// seconds between scheduled refreshes, 0 to only refresh on demand
+    pub refresh_interval_secs: u64,
+}
+
+impl Default for CatalogConfig {
+    fn default() -> CatalogConfig {
+        CatalogConfig {
+            page_size: 20,
+            refresh_interval_secs: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// seconds a cached tag list is served before asking the registry again
+    pub tags_ttl_secs: usize,
+    /// seconds a manifest is kept by digest, tags are resolved to a digest on every request
+    pub manifests_ttl_secs: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            tags_ttl_secs: 300,
+            manifests_ttl_secs: 86400,
+        }
+    }
+}
+
+/// origins allowed to call the api, only read at startup
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// any origin is allowed when empty
+    pub origins: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// json file of retention policies, none are applied when unset
+    pub file: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    /// token expected from registry notifications, tag deletions and retention executions,
+    /// all are refused when unset
+    pub token: Option<String>,
+}
+
+/// built frontend served by the api, and where it is told to find the api
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrontendConfig {
+    /// directory served at `/` when set, only read at startup
+    pub dist: Option<String>,
+    /// address of the api for browsers, sent in `/config.json`, the same origin when empty
+    pub api_base: String,
+}
+
+impl Config {
+    /// loads, overrides and validates the configuration
+    pub fn load() -> Result<Config, anyhow::Error> {
+        let mut config = match env::var("SHIPYARD_CONFIG") {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &str) -> Result<Config, anyhow::Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to read configuration {}: {}",
+                    path, e
+                )))
+            }
+        };
+        match toml::from_str(&content) {
+            Ok(config) => Ok(config),
+            Err(e) => Err(anyhow::Error::msg(format!(
+                "Failed to parse configuration {}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    fn apply_env(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(address) = env_var("SHIPYARD_URL") {
+            self.listen.address = address;
+        }
+        if let Some(port) = parsed_env_var("SHIPYARD_PORT")? {
+            self.listen.port = port;
+        }
+        if let Some(url) = env_var("SHIPYARD_REDIS_URL") {
+            self.redis.url = url;
+        }
+        if let Some(backend) = parsed_env_var("SHIPYARD_STORAGE")? {
+            self.storage.backend = backend;
+        }
+        if let Some(snapshot) = env_var("SHIPYARD_SNAPSHOT_FILE") {
+            self.storage.snapshot = Some(snapshot);
+        }
+        if let Some(page_size) = parsed_env_var("SHIPYARD_REPO_PAGE_SIZE")? {
+            self.catalog.page_size = page_size;
+        }
+        if let Some(interval) = parsed_env_var("SHIPYARD_REFRESH_INTERVAL")? {
+            self.catalog.refresh_interval_secs = interval;
+        }
+        if let Some(ttl) = parsed_env_var("SHIPYARD_TAGS_TTL")? {
+            self.cache.tags_ttl_secs = ttl;
+        }
+        if let Some(ttl) = parsed_env_var("SHIPYARD_MANIFESTS_TTL")? {
+            self.cache.manifests_ttl_secs = ttl;
+        }
+        if let Some(origins) = env_var("SHIPYARD_CORS_ORIGINS") {
+            self.cors.origins = origins
+                .split(',')
+                .map(|o| o.trim().to_string())
+                .filter(|o| !o.is_empty())
+                .collect();
+        }
+        if let Some(file) = env_var("SHIPYARD_RETENTION_FILE") {
+            self.retention.file = Some(file);
+        }
+        if let Some(token) = env_var("SHIPYARD_EVENTS_TOKEN") {
+            self.events.token = Some(token);
+        }
+        if let Some(dist) = env_var("SHIPYARD_FRONTEND_DIST") {
+            self.frontend.dist = Some(dist);
+        }
+        if let Some(api_base) = env_var("SHIPYARD_API_BASE") {
+            self.frontend.api_base = api_base;
+        }
+        // the single registry variables override the first registry, or define it
+        let url = env_var("SHIPYARD_REGISTRY_URL");
+        if self.registries.is_empty() {
+            self.registries.push(RegistryConfig {
+                name: "default".to_string(),
+                url: url
+                    .clone()
+                    .unwrap_or("https://docker.adotmob.com/v2".to_string()),
+                username: None,
+                password: None,
+                timeout_secs: default_timeout(),
+            });
+        }
+        let registry = &mut self.registries[0];
+        if let Some(name) = env_var("SHIPYARD_REGISTRY_NAME") {
+            registry.name = name;
+        }
+        if let Some(url) = url {
+            registry.url = url;
+        }
+        if let Some(username) = env_var("SHIPYARD_REGISTRY_USERNAME") {
+            registry.username = Some(username);
+        }
+        if let Some(password) = env_var("SHIPYARD_REGISTRY_PASSWORD") {
+            registry.password = Some(password);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let mut errors = vec![];
+        if self.catalog.page_size < 1 {
+            errors.push("catalog.page_size must be at least 1".to_string());
+        }
+        // redis refuses an expiry of 0 and the memory store would never serve the entry
+        if self.cache.tags_ttl_secs < 1 {
+            errors.push("cache.tags_ttl_secs must be at least 1".to_string());
+        }
+        if self.cache.manifests_ttl_secs < 1 {
+            errors.push("cache.manifests_ttl_secs must be at least 1".to_string());
+        }
+        match self.storage.backend {
+            StorageBackend::Redis if self.redis.url.is_empty() => {
+                errors.push("redis.url must be set".to_string())
+            }
+            StorageBackend::Redis if self.storage.snapshot.is_some() => {
+                errors.push("storage.snapshot is only used by the memory backend".to_string())
+            }
+            _ => {}
+        }
+        for (i, registry) in self.registries.iter().enumerate() {
+            // names are used as path segments and redis key prefixes
+            if registry.name.is_empty() || registry.name.contains(['/', ':']) {
+                errors.push(format!(
+                    "registries[{}].name {:?} must be non empty without '/' or ':'",
+                    i, registry.name
+                ));
+            }
+            if self.registries[..i].iter().any(|r| r.name == registry.name) {
+                errors.push(format!(
+                    "registries[{}].name {} is used twice",
+                    i, registry.name
+                ));
+            }
+            if !registry.url.starts_with("http://") && !registry.url.starts_with("https://") {
+                errors.push(format!(
+                    "registries[{}].url {:?} must start with http:// or https://",
+                    i, registry.url
+                ));
+            }
+            if registry.username.is_some() != registry.password.is_some() {
+                errors.push(format!(
+                    "registries[{}] must set both username and password, or neither",
+                    i
+                ));
+            }
+            if registry.timeout_secs < 1 {
+                errors.push(format!("registries[{}].timeout_secs must be at least 1", i));
+            }
+        }
+        for origin in self.cors.origins.iter() {
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                errors.push(format!(
+                    "cors origin {:?} must start with http:// or https://",
+                    origin
+                ));
+            }
+        }
+        if let Some(dist) = &self.frontend.dist {
+            if !Path::new(dist).is_dir() {
+                errors.push(format!("frontend.dist {:?} must be a directory", dist));
+            }
+        }
+        let api_base = &self.frontend.api_base;
+        if !api_base.is_empty()
+            && !api_base.starts_with('/')
+            && !api_base.starts_with("http://")
+            && !api_base.starts_with("https://")
+        {
+            errors.push(format!(
+                "frontend.api_base {:?} must be empty, a path or start with http:// or https://",
+                api_base
+            ));
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow::Error::msg(format!(
+                "Invalid configuration:\n  {}",
+                errors.join("\n  ")
+            ))),
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn parsed_env_var<T: FromStr>(name: &str) -> Result<Option<T>, anyhow::Error>
+where
+    T::Err: std::fmt::Display,
+{
+    match env_var(name) {
+        Some(value) => match value.parse::<T>() {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => Err(anyhow::Error::msg(format!(
+                "Invalid {} {:?}: {}",
+                name, value, e
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// shared value replaced as a whole when the configuration is reloaded
+pub struct Live<T: ?Sized>(RwLock<Arc<T>>);
+
+impl<T> Live<T> {
+    pub fn new(value: T) -> Live<T> {
+        Live::from_arc(Arc::new(value))
+    }
+
+    pub fn store(&self, value: T) {
+        self.store_arc(Arc::new(value));
+    }
+}
+
+impl<T: ?Sized> Live<T> {
+    pub fn from_arc(value: Arc<T>) -> Live<T> {
+        Live(RwLock::new(value))
+    }
+
+    /// current value, unaffected by later reloads
+    pub fn load(&self) -> Arc<T> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn store_arc(&self, value: Arc<T>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_zero_ttls() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        config.cache.tags_ttl_secs = 0;
+        config.cache.manifests_ttl_secs = 0;
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("cache.tags_ttl_secs must be at least 1"));
+        assert!(error.contains("cache.manifests_ttl_secs must be at least 1"));
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Deserialize;
+use shipyard::{ApiError, ErrorsV2, ManifestError};
+
+use crate::registry::RegistryResponse;
+
+#[derive(Deserialize)]
+struct RegistryErrors {
+    errors: Vec<ErrorsV2>,
+}
+
+/// error answered by every route as a json `ApiError`
+#[derive(Debug, Clone)]
+pub struct Error {
+    status: StatusCode,
+    body: ApiError,
+}
+
+impl Error {
+    pub fn new(status: StatusCode, code: &str, message: impl Into<String>) -> Error {
+        Error {
+            status,
+            body: ApiError {
+                code: code.to_string(),
+                message: message.into(),
+                upstream_status: None,
+                errors: vec![],
+            },
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Error {
+        Error::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Error {
+        Error::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Error {
+        Error::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Error {
+        Error::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+
+    /// the registry or its token server could not be reached
+    pub fn unreachable(message: impl Into<String>) -> Error {
+        Error::new(StatusCode::BAD_GATEWAY, "upstream_unreachable", message)
+    }
+
+    /// the registry answered `res` instead of a success
+    ///
+    /// statuses about the request itself are passed on, those about the registry or the
+    /// credentials of the backend become a 502
+    pub fn upstream(context: &str, res: &RegistryResponse) -> Error {
+        let (status, code) = match res.status {
+            StatusCode::BAD_REQUEST => (StatusCode::BAD_REQUEST, "bad_request"),
+            StatusCode::NOT_FOUND => (StatusCode::NOT_FOUND, "not_found"),
+            StatusCode::METHOD_NOT_ALLOWED => (StatusCode::METHOD_NOT_ALLOWED, "unsupported"),
+            StatusCode::TOO_MANY_REQUESTS => (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+            _ => (StatusCode::BAD_GATEWAY, "upstream_error"),
+        };
+        let mut error = Error::new(
+            status,
+            code,
+            format!("{}: registry answered {}", context, res.status),
+        );
+        error.body.upstream_status = Some(res.status.as_u16());
+        error.body.errors = serde_json::from_slice::<RegistryErrors>(&res.body)
+            .map(|e| e.errors)
+            .unwrap_or_default();
+        error
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.body.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .content_type("application/json")
+            .body(serde_json::to_string(&self.body).expect("Failed to serialize response"))
+    }
+}
+
+/// keeps the status of the first `Error` in the chain, a 500 otherwise, with the message
+/// of the whole chain
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Error {
+        let message = e
+            .chain()
+            .map(|c| match c.downcast_ref::<Error>() {
+                Some(error) => error.body.message.clone(),
+                None => c.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(": ");
+        let cause = e.chain().find_map(|c| {
+            c.downcast_ref::<Error>()
+                .cloned()
+                .or_else(|| match c.downcast_ref() {
+                    // a registry error body where a manifest was expected
+                    Some(ManifestError::Registry(errors)) => {
+                        let mut error = Error::new(StatusCode::BAD_GATEWAY, "upstream_error", "");
+                        error.body.errors = errors.clone();
+                        Some(error)
+                    }
+                    _ => None,
+                })
+        });
+        let mut error = cause.unwrap_or_else(|| Error::internal(""));
+        error.body.message = message;
+        error
+    }
+}
@@ -0,0 +1,239 @@
+use shipyard::{EventEnvelope, RegistryEvent};
+
+use crate::{registry::Registry, req_tags, store::Store};
+
+/// number of events kept in the activity feed
+const ACTIVITY_LEN: usize = 100;
+
+/// checks an `Authorization` header against the configured token,
+/// every request is refused when the token is not set
+pub fn authorized(token: Option<&str>, header: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) if !token.is_empty() => token,
+        _ => return false,
+    };
+    let header = match header {
+        Some(header) => header.strip_prefix("Bearer ").unwrap_or(header),
+        None => return false,
+    };
+    // compare in constant time so the token cannot be guessed byte by byte
+    header.len() == token.len()
+        && header
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// updates the catalog, tag caches and activity feed from registry notifications,
+/// returns the number of events taken into account
+pub async fn apply(
+    store: &dyn Store,
+    registry: &Registry,
+    envelope: EventEnvelope,
+) -> Result<usize, anyhow::Error> {
+    let ns = registry.name();
+    let mut applied = 0;
+    for event in envelope.events {
+        // pulls and blob uploads do not change the catalog, deletes by digest
+        // come without a media type and are handled as manifest deletes
+        if event.action == "pull" || !event.target.is_manifest() {
+            continue;
+        }
+        let repository = event.target.repository.as_str();
+        let emptied = event.action == "delete"
+            && req_tags(registry, repository)
+                .await
+                .is_ok_and(|tags| tags.tags.is_empty());
+        match (event.action.as_str(), &event.target.tag) {
+            ("push", tag) => {
+                store.add_to_catalog(ns, repository).await?;
+                // only extends lists already cached, others are fetched on demand
+                if let Some(tag) = tag {
+                    store.add_tag(ns, repository, tag).await?;
+                }
+            }
+            ("delete", Some(tag)) => store.remove_tag(ns, repository, tag).await?,
+            // a manifest deleted by digest takes unknown tags with it
+            ("delete", None) => store.invalidate_tags(ns, repository).await?,
+            _ => {}
+        }
+        // the digest is gone whichever tag it was deleted through
+        if let ("delete", Some(digest)) = (event.action.as_str(), &event.target.digest) {
+            store.invalidate_manifest(ns, repository, digest).await?;
+        }
+        if emptied {
+            store.remove_from_catalog(ns, repository).await?;
+            store.invalidate_tags(ns, repository).await?;
+        }
+        store
+            .push_activity(ns, &serde_json::to_string(&event)?, ACTIVITY_LEN)
+            .await?;
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// most recent events first
+pub async fn activity(
+    store: &dyn Store,
+    registry: &Registry,
+) -> Result<Vec<RegistryEvent>, anyhow::Error> {
+    let events = store.activity(registry.name()).await?;
+    Ok(events
+        .iter()
+        .filter_map(|e| serde_json::from_str(e).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse};
+    use shipyard::EventTarget;
+
+    use super::*;
+    use crate::{config::RegistryConfig, store::MemoryStore};
+
+    /// only `app` still has tags, every other repository is empty
+    async fn tags(web::Path(image): web::Path<String>) -> HttpResponse {
+        let tags: &[&str] = if image == "app" { &["1.0"] } else { &[] };
+        HttpResponse::Ok().json(serde_json::json!({"name": image, "tags": tags}))
+    }
+
+    fn server() -> test::TestServer {
+        test::start(|| App::new().route("/v2/{image}/tags/list", web::get().to(tags)))
+    }
+
+    fn registry(srv: &test::TestServer) -> Registry {
+        Registry::new(&RegistryConfig {
+            name: "hub".to_string(),
+            url: srv.url("/v2"),
+            username: None,
+            password: None,
+            timeout_secs: 5,
+        })
+    }
+
+    fn event(
+        action: &str,
+        repository: &str,
+        tag: Option<&str>,
+        media_type: Option<&str>,
+    ) -> RegistryEvent {
+        RegistryEvent {
+            action: action.to_string(),
+            target: EventTarget {
+                media_type: media_type.map(String::from),
+                digest: Some("sha256:0123".to_string()),
+                repository: repository.to_string(),
+                tag: tag.map(String::from),
+                ..EventTarget::default()
+            },
+            ..RegistryEvent::default()
+        }
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|i| i.to_string()).collect()
+    }
+
+    /// store with `app` and `gone` in the catalog and their tags cached
+    async fn store() -> MemoryStore {
+        let store = MemoryStore::open(None).unwrap();
+        store
+            .replace_catalog("hub", &strings(&["app", "gone"]))
+            .await
+            .unwrap();
+        for image in ["app", "gone"] {
+            store
+                .set_tags("hub", image, &strings(&["1.0", "2.0"]), 60)
+                .await
+                .unwrap();
+        }
+        store
+    }
+
+    async fn apply_all(
+        store: &MemoryStore,
+        registry: &Registry,
+        events: Vec<RegistryEvent>,
+    ) -> usize {
+        apply(store, registry, EventEnvelope { events })
+            .await
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn applies_tagged_pushes() {
+        let srv = server();
+        let (store, registry) = (store().await, registry(&srv));
+        let manifest = "application/vnd.oci.image.manifest.v1+json";
+        let events = vec![
+            event("push", "app", Some("3.0"), Some(manifest)),
+            event("push", "new", Some("1.0"), Some(manifest)),
+            event(
+                "push",
+                "app",
+                None,
+                Some("application/vnd.oci.image.layer.v1.tar+gzip"),
+            ),
+            event("pull", "app", Some("1.0"), Some(manifest)),
+        ];
+        assert_eq!(apply_all(&store, &registry, events).await, 2);
+        assert_eq!(
+            store.catalog_range("hub", 0, 10).await.unwrap(),
+            (3, strings(&["app", "gone", "new"]))
+        );
+        assert_eq!(
+            store.tags("hub", "app").await.unwrap(),
+            Some(strings(&["1.0", "2.0", "3.0"]))
+        );
+        // tags of unknown repositories are fetched on demand
+        assert_eq!(store.tags("hub", "new").await.unwrap(), None);
+        assert_eq!(store.activity("hub").await.unwrap().len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn applies_tagged_deletes() {
+        let srv = server();
+        let (store, registry) = (store().await, registry(&srv));
+        let events = vec![event("delete", "app", Some("2.0"), None)];
+        assert_eq!(apply_all(&store, &registry, events).await, 1);
+        assert_eq!(
+            store.tags("hub", "app").await.unwrap(),
+            Some(strings(&["1.0"]))
+        );
+        assert_eq!(store.catalog_range("hub", 0, 10).await.unwrap().0, 2);
+    }
+
+    #[actix_rt::test]
+    async fn applies_digest_only_deletes() {
+        let srv = server();
+        let (store, registry) = (store().await, registry(&srv));
+        store
+            .set_manifest("hub", "app", "sha256:0123", "{}", 60)
+            .await
+            .unwrap();
+        let events = vec![event("delete", "app", None, None)];
+        assert_eq!(apply_all(&store, &registry, events).await, 1);
+        // the deleted manifest may have carried any of the tags
+        assert_eq!(store.tags("hub", "app").await.unwrap(), None);
+        assert_eq!(
+            store.manifest("hub", "app", "sha256:0123").await.unwrap(),
+            None
+        );
+        assert_eq!(
+            store.catalog_range("hub", 0, 10).await.unwrap(),
+            (2, strings(&["app", "gone"]))
+        );
+        // deleting the last manifest empties the repository
+        let events = vec![event("delete", "gone", None, None)];
+        assert_eq!(apply_all(&store, &registry, events).await, 1);
+        assert_eq!(store.tags("hub", "gone").await.unwrap(), None);
+        assert_eq!(
+            store.catalog_range("hub", 0, 10).await.unwrap(),
+            (1, strings(&["app"]))
+        );
+        assert_eq!(store.activity("hub").await.unwrap().len(), 2);
+    }
+}
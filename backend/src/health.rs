@@ -0,0 +1,59 @@
+use std::{sync::Arc, time::Duration};
+
+use shipyard::{Readiness, RegistryReadiness};
+
+use crate::{
+    refresh::Refreshers,
+    registry::{Registries, Registry},
+    store::Store,
+};
+
+/// how long an answer from a registry proves it reachable, it is pinged afterwards
+const RECENT: Duration = Duration::from_secs(120);
+
+/// checks the storage and every registry, registries which did not answer recently are
+/// pinged in the background so that probes never wait on them
+pub async fn readiness(
+    store: &dyn Store,
+    registries: &Registries,
+    refreshers: &Refreshers,
+) -> Readiness {
+    let storage_error = store.ping().await.err().map(|e| e.to_string());
+    let mut res = Readiness {
+        ready: storage_error.is_none(),
+        storage_error,
+        registries: vec![],
+    };
+    for registry in registries.iter() {
+        let registry = registry_readiness(store, registry, refreshers).await;
+        res.ready &= registry.reachable && registry.catalog_ready;
+        res.registries.push(registry);
+    }
+    res
+}
+
+async fn registry_readiness(
+    store: &dyn Store,
+    registry: &Arc<Registry>,
+    refreshers: &Refreshers,
+) -> RegistryReadiness {
+    let last_seen = registry.last_seen();
+    let reachable = last_seen.is_some_and(|seen| seen <= RECENT);
+    if !reachable {
+        registry.ping_later();
+    }
+    let catalog_size = store.catalog_len(registry.name()).await.ok();
+    // an empty catalog is fine once the registry confirmed it is empty
+    let refreshed = refreshers.get(registry.name()).is_some_and(|r| {
+        let status = r.status();
+        status.last_count.is_some() && status.last_error.is_none()
+    });
+    RegistryReadiness {
+        name: registry.name().to_string(),
+        reachable,
+        last_seen_secs: last_seen.map(|seen| seen.as_secs()),
+        error: registry.ping_error().filter(|_| !reachable),
+        catalog_ready: catalog_size.is_some_and(|size| size > 0) || refreshed,
+        catalog_size,
+    }
+}
@@ -1,250 +1,1389 @@
-use std::{
-    env, io,
-    sync::{Arc, Mutex, MutexGuard},
-    time::Duration,
-};
+mod cache;
+mod compare;
+mod config;
+mod error;
+mod events;
+mod health;
+mod metrics;
+mod refresh;
+mod registry;
+mod retention;
+mod store;
+
+use std::{io, sync::Arc, time::Instant};
 
 use actix_cors::Cors;
-use actix_web::{client::ClientBuilder, get, web, App, HttpResponse, HttpServer};
-use itertools::Itertools;
-use redis::{Client, Commands};
-use shipyard::{Repos, Tags};
+use actix_files::Files;
+use actix_web::{
+    delete,
+    dev::Service,
+    get,
+    http::{header, Method, StatusCode},
+    post,
+    rt::{
+        self,
+        signal::unix::{signal, SignalKind},
+    },
+    web,
+    web::Bytes,
+    App, HttpRequest, HttpResponse, HttpServer, ResponseError,
+};
+use config::{Config, Live, StorageBackend};
+use error::Error;
+use futures::{stream, StreamExt};
+use refresh::{Refresher, Refreshers};
+use registry::{Registries, Registry, Selected};
+use retention::Policy;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use shipyard::{
+    url_decode, url_encode, CatalogPage, ClientConfig, DeleteReport, DockerManifest, EventEnvelope,
+    ImageConfig, ImageSize, PlatformSize, RefreshJob, Repos, Tags, MANIFEST_MEDIA_TYPES,
+};
+use store::Store;
+
+/// storage shared by every worker, replaced when the configuration is reloaded
+type Storage = Live<dyn Store>;
 
+/// images asked from the registry per `_catalog` request when refreshing a catalog, the
+/// `catalog.page_size` of the api is sized for the frontend and would cost many requests
+const REGISTRY_PAGE_SIZE: usize = 300;
+
+/// digests resolved at once when looking for the tags that share the one being deleted
+const DIGEST_REQUESTS: usize = 8;
+
+/// reads the whole catalog from the registry, `page_size` images at a time or all at once
+/// when 0, and replaces the stored one
 async fn req_list_images(
     page_size: usize,
-    redis: MutexGuard<'_, Client>,
+    storage: &Storage,
+    registry: &Registry,
 ) -> Result<usize, anyhow::Error> {
     let mut res_repos = Repos::default();
-    let url = format!(
-        "{}/_catalog",
-        env::var("SHIPYARD_REGISTRY_URL").unwrap_or("https://docker.adotmob.com/v2".to_string())
-    );
-    let mut last = "";
+    let mut last: Option<String> = None;
     loop {
-        let url_req = match (page_size == 0, last.is_empty()) {
-            (true, false) => format!("{}?last={}", url, last),
-            (true, true) => url.to_string(),
-            (false, false) => format!("{}?n={}&last={}", url, page_size, last),
-            (false, true) => format!("{}?n={}", url, page_size),
+        let path = match (page_size == 0, &last) {
+            (true, Some(last)) => format!("/_catalog?last={}", last),
+            (true, None) => "/_catalog".to_string(),
+            (false, Some(last)) => format!("/_catalog?n={}&last={}", page_size, last),
+            (false, None) => format!("/_catalog?n={}", page_size),
         };
-        match ClientBuilder::new()
-            .timeout(Duration::from_secs(60))
-            .finish()
-            .get(url_req)
-            .send()
-            .await
-        {
+        let res = match registry.get(&path, None).await {
+            Ok(res) if res.status.is_success() => res,
+            Ok(res) => {
+                return Err(Error::upstream("Failed to request docker directory", &res).into())
+            }
             Err(e) => {
-                return Err(anyhow::Error::msg(format!(
+                return Err(Error::unreachable(format!(
                     "Failed to request docker directory: {}",
                     e
+                ))
+                .into())
+            }
+        };
+        let mut repos = match serde_json::from_slice::<Repos>(&res.body) {
+            Ok(repos) => repos,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to parse docker directory response: {}",
+                    e
                 )))
             }
-            Ok(mut res) => match res.json::<Repos>().await {
-                Err(e) => {
-                    return Err(anyhow::Error::msg(format!(
-                        "Failed to parse docker directory response: {}",
-                        e
-                    )))
-                }
-                Ok(mut repos) => {
-                    if repos.repositories.len() < page_size {
-                        let mut con = redis.get_connection().expect("Failed to get connection");
-                        let _: () = con.del("catalog").expect("Failed to clear catalog");
-                        for i in res_repos.repositories.iter() {
-                            let _: () = con.sadd("catalog", i).expect("Failed to set catalog");
-                        }
-                        return Ok(res_repos.repositories.len());
-                    }
-                    res_repos.repositories.append(repos.repositories.as_mut());
-                    last = match res_repos.repositories.last() {
-                        None => return Ok(0),
-                        Some(i) => i,
-                    };
-                }
-            },
+        };
+        // the registry announces further pages with a `Link` header
+        let done = repos.repositories.is_empty() || !res.headers.contains_key(header::LINK);
+        res_repos.repositories.append(repos.repositories.as_mut());
+        if done {
+            break;
         }
+        last = res_repos.repositories.last().cloned();
+    }
+    if let Err(e) = storage
+        .load()
+        .replace_catalog(registry.name(), &res_repos.repositories)
+        .await
+    {
+        return Err(anyhow::Error::msg(format!("Failed to set catalog: {}", e)));
+    }
+    Ok(res_repos.repositories.len())
+}
+
+#[get("/registries")]
+async fn list_registries(registries: web::Data<Arc<Live<Registries>>>) -> HttpResponse {
+    let registries = registries.load();
+    let names: Vec<&str> = registries.iter().map(|r| r.name()).collect();
+    HttpResponse::Ok().body(serde_json::to_string(&names).expect("Failed to serialize response"))
+}
+
+/// answers as long as the process is up
+async fn unknown_route(req: HttpRequest) -> HttpResponse {
+    Error::not_found(format!("No route for {} {}", req.method(), req.path())).error_response()
+}
+
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// answers 503 until the storage is reachable and every registry answered recently
+/// with a populated catalog
+#[get("/readyz")]
+async fn readyz(
+    storage: web::Data<Arc<Storage>>,
+    registries: web::Data<Arc<Live<Registries>>>,
+    refreshers: web::Data<Arc<Live<Refreshers>>>,
+) -> HttpResponse {
+    let res = health::readiness(&*storage.load(), &registries.load(), &refreshers.load()).await;
+    match res.ready {
+        true => HttpResponse::Ok(),
+        false => HttpResponse::ServiceUnavailable(),
     }
+    .body(serde_json::to_string(&res).expect("Failed to serialize response"))
+}
+
+#[get("/metrics")]
+async fn get_metrics(
+    storage: web::Data<Arc<Storage>>,
+    registries: web::Data<Arc<Live<Registries>>>,
+    refreshers: web::Data<Arc<Live<Refreshers>>>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(&*storage.load(), &registries.load(), &refreshers.load()).await)
+}
+
+/// OpenAPI document of every route, generated from the endpoints of the library
+#[get("/openapi.json")]
+async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok().content_type("application/json").body(
+        serde_json::to_string(&shipyard::api::openapi()).expect("Failed to serialize response"),
+    )
+}
+
+/// tells the frontend where the api is, in place of a `config.json` of the served directory
+#[get("/config.json")]
+async fn client_config(config: web::Data<Arc<Live<Config>>>) -> HttpResponse {
+    let res = ClientConfig {
+        api_base: config.load().frontend.api_base.clone(),
+    };
+    HttpResponse::Ok().body(serde_json::to_string(&res).expect("Failed to serialize response"))
+}
+
+#[get("/cache")]
+async fn cache_stats() -> HttpResponse {
+    HttpResponse::Ok()
+        .body(serde_json::to_string(&cache::stats()).expect("Failed to serialize response"))
 }
 
 #[get("/refresh_catalog")]
-async fn refresh_catalog(client: web::Data<Arc<Mutex<Client>>>) -> HttpResponse {
-    match client.lock() {
-        Ok(client) => match req_list_images(300, client).await {
-            Ok(s) => HttpResponse::Ok().body(format!("refreshed catalog\nnb images: {}", s)),
-            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+async fn refresh_catalog(
+    registry: Selected,
+    refreshers: web::Data<Arc<Live<Refreshers>>>,
+) -> HttpResponse {
+    match refreshers.load().get(registry.name()) {
+        Some(refresher) => {
+            let job = RefreshJob {
+                job: refresher.trigger(),
+            };
+            HttpResponse::Accepted()
+                .body(serde_json::to_string(&job).expect("Failed to serialize response"))
+        }
+        None => Error::not_found(format!("No refresher for {}", registry.name())).error_response(),
     }
 }
 
+#[get("/refresh_status")]
+async fn refresh_status(
+    registry: Selected,
+    refreshers: web::Data<Arc<Live<Refreshers>>>,
+) -> HttpResponse {
+    match refreshers.load().get(registry.name()) {
+        Some(refresher) => HttpResponse::Ok().body(
+            serde_json::to_string(&refresher.status()).expect("Failed to serialize response"),
+        ),
+        None => Error::not_found(format!("No refresher for {}", registry.name())).error_response(),
+    }
+}
+
+/// reads `page_size` images of the catalog starting at `offset`
+async fn read_catalog_page(
+    store: &dyn Store,
+    registry: &Registry,
+    offset: usize,
+    page_size: usize,
+) -> Result<CatalogPage, anyhow::Error> {
+    let (total, repositories) = store
+        .catalog_range(registry.name(), offset, page_size)
+        .await?;
+    Ok(CatalogPage {
+        next: next_cursor(&repositories, offset, total),
+        repositories,
+        page: offset / page_size + 1,
+        page_size,
+        total,
+    })
+}
+
+/// the last image of a page when more follow, to be sent back as `last`
+fn next_cursor(repositories: &[String], offset: usize, total: usize) -> Option<String> {
+    match offset + repositories.len() < total {
+        true => repositories.last().cloned(),
+        false => None,
+    }
+}
+
+fn catalog_response(registry: &Registry, page: &CatalogPage) -> HttpResponse {
+    let mut res = HttpResponse::Ok();
+    // same convention as the registry's own `/_catalog`
+    if let Some(next) = &page.next {
+        res.header(
+            header::LINK,
+            format!(
+                "</v2/{}/catalog?n={}&last={}>; rel=\"next\"",
+                registry.name(),
+                page.page_size,
+                url_encode(next)
+            ),
+        );
+    }
+    res.body(serde_json::to_string(page).expect("Failed to serialize response"))
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    page_size: Option<usize>,
+}
+
 #[get("/catalog/{page}")]
 async fn list_images_page(
-    web::Path(page): web::Path<usize>,
-    client: web::Data<Arc<Mutex<Client>>>,
-) -> HttpResponse {
-    let page_size = {
-        match env::var("SHIPYARD_REPO_PAGE_SIZE") {
-            Ok(ps) => match ps.parse::<usize>() {
-                Ok(ps) => ps,
-                Err(_) => {
-                    eprintln!("Error parsing page size\ndefaulting to 20...");
-                    20
-                }
-            },
-            Err(_) => {
-                eprintln!("Error parsing page size\ndefaulting to 20...");
-                20
-            }
-        }
+    web::Path((_, page)): web::Path<(String, usize)>,
+    web::Query(query): web::Query<PageQuery>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let page_size = query.page_size.unwrap_or(config.load().catalog.page_size);
+    if page_size < 1 || page < 1 {
+        return Error::bad_request(format!(
+            "Invalid page {} or page size {}, both start at 1",
+            page, page_size
+        ))
+        .error_response();
+    }
+    match read_catalog_page(
+        &*storage.load(),
+        &registry,
+        page_size * (page - 1),
+        page_size,
+    )
+    .await
+    {
+        // the first page exists even when the catalog is empty
+        Ok(res) if res.repositories.is_empty() && page > 1 => Error::not_found(format!(
+            "Page {} is past the last page {}",
+            page,
+            res.total.div_ceil(page_size)
+        ))
+        .error_response(),
+        Ok(res) => catalog_response(&registry, &res),
+        Err(e) => Error::from(e.context("Failed to request page")).error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CursorQuery {
+    n: Option<usize>,
+    last: Option<String>,
+}
+
+/// images following `last` in alphabetical order, like the registry's `/_catalog`
+#[get("/catalog")]
+async fn list_images_cursor(
+    web::Query(query): web::Query<CursorQuery>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let page_size = query.n.unwrap_or(config.load().catalog.page_size);
+    if page_size < 1 {
+        return Error::bad_request(format!("Invalid page size {}, it starts at 1", page_size))
+            .error_response();
+    }
+    let store = storage.load();
+    let offset = match query.last {
+        Some(last) if !last.is_empty() => match store.catalog_rank(registry.name(), &last).await {
+            Ok(offset) => offset,
+            Err(e) => return Error::from(e.context("Failed to locate cursor")).error_response(),
+        },
+        _ => 0,
     };
-    match client.lock() {
-        Ok(client) => match client.get_connection() {
-            Ok(mut con) => match con.scard("catalog") {
-                Ok(card) => {
-                    let card: usize = card;
-                    if page_size < 1 || page < 1 || card / page_size < page {
-                        return HttpResponse::NotFound().body(format!(
-                            "invalid page or page size\nmax page: {}\npage: {}",
-                            card / page_size,
-                            page
-                        ));
-                    };
-                    match redis::cmd("SORT")
-                        .arg(&[
-                            "catalog",
-                            "alpha",
-                            "limit",
-                            ((card / page_size * (page - 1)) as usize).to_string().as_ref(),
-                            page_size.to_string().as_ref(),
-                        ])
-                        .query(&mut con)
-                    {
-                        Ok(vec) => {
-                            let repos = Repos { repositories: vec };
-                            HttpResponse::Ok().body(
-                                serde_json::to_string(&repos)
-                                    .expect("Failed to serialize response"),
-                            )
-                        }
-                        Err(e) => HttpResponse::InternalServerError()
-                            .body(format!("Failed to request page: {}", e)),
-                    }
-                }
-                Err(e) => HttpResponse::InternalServerError()
-                    .body(format!("Failed to request catalog size: {}", e)),
-            },
-            Err(e) => HttpResponse::InternalServerError()
-                .body(format!("Failed to get redis connection: {}", e)),
+    match read_catalog_page(&*store, &registry, offset, page_size).await {
+        Ok(res) => catalog_response(&registry, &res),
+        Err(e) => Error::from(e.context("Failed to request page")).error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+    namespace: Option<String>,
+    prefix: Option<String>,
+    page: Option<usize>,
+    page_size: Option<usize>,
+}
+
+/// repositories starting with `namespace/` and `prefix`, then containing `q` ignoring case
+#[get("/search")]
+async fn search_images(
+    web::Query(query): web::Query<SearchQuery>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let page = query.page.unwrap_or(1);
+    let page_size = query.page_size.unwrap_or(config.load().catalog.page_size);
+    if page < 1 || page_size < 1 {
+        return Error::bad_request(format!(
+            "Invalid page {} or page size {}, both start at 1",
+            page, page_size
+        ))
+        .error_response();
+    }
+    let prefix = match (query.namespace, query.prefix) {
+        (Some(namespace), prefix) if !namespace.is_empty() => format!(
+            "{}/{}",
+            namespace.trim_end_matches('/'),
+            prefix.unwrap_or_default()
+        ),
+        (_, prefix) => prefix.unwrap_or_default(),
+    };
+    let found = storage
+        .load()
+        .catalog_prefixed(registry.name(), &prefix)
+        .await;
+    let found = match found {
+        Ok(found) => found,
+        Err(e) => return Error::from(e.context("Failed to search catalog")).error_response(),
+    };
+    let q = query.q.unwrap_or_default().to_lowercase();
+    let matching: Vec<String> = found
+        .into_iter()
+        .filter(|r| r.to_lowercase().contains(&q))
+        .collect();
+    let offset = page_size * (page - 1);
+    let total = matching.len();
+    let repositories: Vec<String> = matching.into_iter().skip(offset).take(page_size).collect();
+    let res = CatalogPage {
+        next: next_cursor(&repositories, offset, total),
+        repositories,
+        page,
+        page_size,
+        total,
+    };
+    HttpResponse::Ok().body(serde_json::to_string(&res).expect("Failed to serialize response"))
+}
+
+/// image and reference of an `image:reference` path segment
+///
+/// the router decodes everything but `%2F`, so the slashes of namespaced images are
+/// decoded here
+fn image_reference(segment: &str) -> Result<(String, String), Error> {
+    let segment = url_decode(segment);
+    match segment.split_once(':') {
+        Some((image, reference)) => Ok((image.to_string(), reference.to_string())),
+        None => Err(Error::bad_request(format!(
+            "Expected image:tag, got {}",
+            segment
+        ))),
+    }
+}
+
+async fn req_tags(registry: &Registry, image: &str) -> Result<Tags, anyhow::Error> {
+    match registry.get(&format!("/{}/tags/list", image), None).await {
+        Err(e) => Err(Error::unreachable(format!("Failed to request tags: {}", e)).into()),
+        Ok(res) if !res.status.is_success() => {
+            Err(Error::upstream(&format!("Failed to request tags of {}", image), &res).into())
+        }
+        Ok(tags) => match serde_json::from_slice::<Tags>(&tags.body) {
+            Ok(tags) => Ok(tags),
+            Err(e) => Err(anyhow::Error::msg(format!("Failed to parse tags: {}", e))),
         },
-        Err(e) => HttpResponse::InternalServerError()
-            .body(format!("Failed to get redis client lock: {}", e)),
     }
 }
 
 #[get("/tags/{image}")]
-async fn list_tags(web::Path(image): web::Path<String>) -> HttpResponse {
-    let url = format!(
-        "{}/{}/tags/list",
-        env::var("SHIPYARD_REGISTRY_URL").unwrap_or("https://docker.adotmob.com/v2".to_string()),
-        image
-    );
-    match ClientBuilder::new()
-        .timeout(Duration::from_secs(60))
-        .finish()
-        .get(url)
-        .send()
+async fn list_tags(
+    web::Path((_, image)): web::Path<(String, String)>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let ttl = config.load().cache.tags_ttl_secs;
+    match cache::tags(&*storage.load(), &registry, &url_decode(&image), ttl).await {
+        Ok(tags) => HttpResponse::Ok()
+            .body(serde_json::to_string(&tags).expect("Failed to serialize response")),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+async fn req_manifest(
+    registry: &Registry,
+    image: &str,
+    reference: &str,
+) -> Result<String, anyhow::Error> {
+    match registry
+        .get(
+            &format!("/{}/manifests/{}", image, reference),
+            Some(&MANIFEST_MEDIA_TYPES.join(", ")),
+        )
+        .await
+    {
+        Err(e) => Err(Error::unreachable(format!("Failed to request manifest: {}", e)).into()),
+        Ok(res) if !res.status.is_success() => Err(Error::upstream(
+            &format!("Failed to request manifest {}:{}", image, reference),
+            &res,
+        )
+        .into()),
+        Ok(manifest) => match std::str::from_utf8(&manifest.body) {
+            Ok(body) => Ok(body.to_string()),
+            Err(e) => Err(anyhow::Error::msg(format!(
+                "Failed to parse manifest: {}",
+                e
+            ))),
+        },
+    }
+}
+
+async fn req_digest(
+    registry: &Registry,
+    image: &str,
+    reference: &str,
+) -> Result<String, anyhow::Error> {
+    Ok(req_resolve(registry, image, reference).await?.0)
+}
+
+/// digest of an image by tag, with its manifest when it had to be fetched to compute it
+async fn req_resolve(
+    registry: &Registry,
+    image: &str,
+    reference: &str,
+) -> Result<(String, Option<String>), anyhow::Error> {
+    let res = match registry
+        .send(
+            Method::HEAD,
+            &format!("/{}/manifests/{}", image, reference),
+            Some(&MANIFEST_MEDIA_TYPES.join(", ")),
+        )
         .await
     {
+        Ok(res) if res.status.is_success() => res,
+        Ok(res) => {
+            return Err(Error::upstream(
+                &format!("Failed to resolve digest of {}:{}", image, reference),
+                &res,
+            )
+            .into())
+        }
         Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to request tags: {}", e))
+            return Err(Error::unreachable(format!(
+                "Failed to resolve digest of {}:{}: {}",
+                image, reference, e
+            ))
+            .into())
+        }
+    };
+    // proxies in front of the registry may drop its own header but keep the ETag, when
+    // both are gone the digest is the one of the manifest body
+    match res
+        .headers
+        .get("Docker-Content-Digest")
+        .or_else(|| res.headers.get(header::ETAG))
+        .and_then(|d| d.to_str().ok())
+        .map(|d| d.trim_matches('"'))
+        .filter(|d| d.contains(':'))
+    {
+        Some(digest) => Ok((digest.to_string(), None)),
+        None => {
+            let manifest = req_manifest(registry, image, reference).await?;
+            Ok((sha256_digest(manifest.as_bytes()), Some(manifest)))
         }
-        Ok(mut tags) => match tags.json::<Tags>().await {
-            Ok(tags) => HttpResponse::Ok()
-                .body(serde_json::to_string(&tags).expect("Failed to serialize response")),
-            Err(e) => {
-                HttpResponse::InternalServerError().body(format!("Failed to parse tags: {}", e))
-            }
-        },
     }
 }
 
-#[get("/manifest/{image}")]
-async fn get_manifest(web::Path(image): web::Path<String>) -> HttpResponse {
-    let (image, tag) = match image.splitn(2, ":").collect_tuple() {
+/// content digest of `content`, as the registry computes it
+fn sha256_digest(content: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(content))
+}
+
+async fn req_blob(registry: &Registry, image: &str, digest: &str) -> Result<Bytes, anyhow::Error> {
+    match registry
+        .get(&format!("/{}/blobs/{}", image, digest), None)
+        .await
+    {
+        Err(e) => Err(Error::unreachable(format!("Failed to request blob: {}", e)).into()),
+        Ok(res) if !res.status.is_success() => {
+            Err(Error::upstream("Failed to request blob", &res).into())
+        }
+        Ok(res) => Ok(res.body),
+    }
+}
+
+async fn req_image_config(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+    tag: &str,
+    ttl: usize,
+) -> Result<ImageConfig, anyhow::Error> {
+    let mut manifest =
+        shipyard::get_manifest(&cache::manifest(store, registry, image, tag, ttl).await?)?;
+    // manifest lists have no config of their own, use the first runnable platform
+    if let Some(platforms) = manifest.platforms() {
+        let digest = match platforms
+            .iter()
+            .find(|p| p.platform.as_ref().is_some_and(|p| p.os != "unknown"))
+        {
+            Some(p) => p.digest.clone(),
+            None => return Err(anyhow::Error::msg("Manifest list has no image platform")),
+        };
+        manifest =
+            shipyard::get_manifest(&cache::manifest(store, registry, image, &digest, ttl).await?)?;
+    }
+    req_config(registry, image, &manifest).await
+}
+
+/// config blob of a single platform manifest
+async fn req_config(
+    registry: &Registry,
+    image: &str,
+    manifest: &DockerManifest,
+) -> Result<ImageConfig, anyhow::Error> {
+    let digest = match manifest.config_digest() {
+        Some(digest) => digest.to_string(),
+        None => return Err(anyhow::Error::msg("Manifest has no config blob")),
+    };
+    let blob = req_blob(registry, image, &digest).await?;
+    match serde_json::from_slice::<ImageConfig>(&blob) {
+        Ok(config) => Ok(config),
+        Err(e) => Err(anyhow::Error::msg(format!(
+            "Failed to parse image config: {}",
+            e
+        ))),
+    }
+}
+
+async fn req_image_size(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+    tag: &str,
+    ttl: usize,
+) -> Result<ImageSize, anyhow::Error> {
+    let manifest =
+        shipyard::get_manifest(&cache::manifest(store, registry, image, tag, ttl).await?)?;
+    let platforms = match manifest.platforms() {
+        Some(platforms) => platforms,
         None => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to parse image and/or tag"))
+            return Ok(ImageSize {
+                size: manifest.size(),
+                platforms: vec![],
+            })
         }
-        Some((img, tg)) => (img, tg),
     };
-    let url = format!(
-        "{}/{}/manifests/{}",
-        env::var("SHIPYARD_REGISTRY_URL").unwrap_or("https://docker.adotmob.com/v2".to_string()),
-        image,
-        tag
-    );
-    match ClientBuilder::new()
-        .timeout(Duration::from_secs(60))
-        .header(
-            "Accept",
-            "application/vnd.docker.distribution.manifest.list.v2+json",
+    let mut sizes = ImageSize::default();
+    // attestations and other artifacts are listed with an unknown platform
+    for platform in platforms
+        .iter()
+        .filter(|p| p.platform.as_ref().is_none_or(|p| p.os != "unknown"))
+    {
+        let child = shipyard::get_manifest(
+            &cache::manifest(store, registry, image, &platform.digest, ttl).await?,
+        )?;
+        sizes.platforms.push(PlatformSize {
+            digest: platform.digest.clone(),
+            platform: platform.platform.clone(),
+            size: child.size(),
+        });
+    }
+    sizes.size = Some(sizes.platforms.iter().filter_map(|p| p.size).sum());
+    Ok(sizes)
+}
+
+#[get("/manifest/{image}")]
+async fn get_manifest(
+    web::Path((_, image)): web::Path<(String, String)>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let (image, tag) = match image_reference(&image) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.error_response(),
+    };
+    let (image, tag) = (image.as_str(), tag.as_str());
+    let ttl = config.load().cache.manifests_ttl_secs;
+    match cache::digest_manifest(&*storage.load(), &registry, image, tag, ttl).await {
+        Ok((digest, manifest)) => HttpResponse::Ok()
+            .header("Docker-Content-Digest", digest)
+            .body(manifest),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+#[get("/config/{image}")]
+async fn get_config(
+    web::Path((_, image)): web::Path<(String, String)>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let (image, tag) = match image_reference(&image) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.error_response(),
+    };
+    let (image, tag) = (image.as_str(), tag.as_str());
+    let ttl = config.load().cache.manifests_ttl_secs;
+    match req_image_config(&*storage.load(), &registry, image, tag, ttl).await {
+        Ok(config) => HttpResponse::Ok()
+            .body(serde_json::to_string(&config).expect("Failed to serialize response")),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+#[get("/size/{image}")]
+async fn get_size(
+    web::Path((_, image)): web::Path<(String, String)>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let (image, tag) = match image_reference(&image) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.error_response(),
+    };
+    let (image, tag) = (image.as_str(), tag.as_str());
+    let ttl = config.load().cache.manifests_ttl_secs;
+    match req_image_size(&*storage.load(), &registry, image, tag, ttl).await {
+        Ok(size) => HttpResponse::Ok()
+            .body(serde_json::to_string(&size).expect("Failed to serialize response")),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+async fn uncatalog(
+    store: &dyn Store,
+    registry: &Registry,
+    image: &str,
+) -> Result<(), anyhow::Error> {
+    match store.remove_from_catalog(registry.name(), image).await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(anyhow::Error::msg(format!(
+            "Failed to remove {} from catalog: {}",
+            image, e
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    other_image: Option<String>,
+    other_reference: String,
+    platform: Option<String>,
+}
+
+/// changes from `image:tag` to `other_image:other_reference`, in the same image by default
+#[get("/compare/{image}")]
+async fn compare_images(
+    web::Path((_, image)): web::Path<(String, String)>,
+    web::Query(query): web::Query<CompareQuery>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let (image, tag) = match image_reference(&image) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.error_response(),
+    };
+    let (image, tag) = (image.as_str(), tag.as_str());
+    let other_image = query.other_image.as_deref().unwrap_or(image);
+    let ttl = config.load().cache.manifests_ttl_secs;
+    match compare::compare(
+        &*storage.load(),
+        &registry,
+        (image, tag),
+        (other_image, &query.other_reference),
+        query.platform.as_deref(),
+        ttl,
+    )
+    .await
+    {
+        Ok(diff) => HttpResponse::Ok()
+            .body(serde_json::to_string(&diff).expect("Failed to serialize response")),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    force: Option<bool>,
+}
+
+#[delete("/manifest/{image}")]
+async fn delete_manifest(
+    req: HttpRequest,
+    web::Path((_, image)): web::Path<(String, String)>,
+    web::Query(query): web::Query<DeleteQuery>,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    if let Err(e) = check_token(&req, &config.load()) {
+        return e.error_response();
+    }
+    let (image, tag) = match image_reference(&image) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.error_response(),
+    };
+    let (image, tag) = (image.as_str(), tag.as_str());
+    let digest = match req_digest(&registry, image, tag).await {
+        Ok(digest) => digest,
+        Err(e) => return Error::from(e).error_response(),
+    };
+    let tags = match req_tags(&registry, image).await {
+        Ok(tags) => tags,
+        Err(e) => return Error::from(e).error_response(),
+    };
+    // deleting a digest deletes every tag pointing to it
+    let mut report = DeleteReport {
+        digest,
+        tags: vec![],
+        removed_from_catalog: false,
+    };
+    if tags.tags.iter().any(|t| t == tag) {
+        report.tags.push(tag.to_string());
+    }
+    let digests = {
+        let registry: &Registry = &registry;
+        stream::iter(tags.tags.into_iter().filter(|t| t != tag))
+            .map(|other| async move {
+                let digest = req_digest(registry, image, &other).await;
+                (other, digest)
+            })
+            .buffered(DIGEST_REQUESTS)
+            .collect::<Vec<_>>()
+            .await
+    };
+    for (other, digest) in digests {
+        match digest.map_err(Error::from) {
+            Ok(digest) if digest == report.digest => report.tags.push(other),
+            Ok(_) => {}
+            // untagged since the tags were listed
+            Err(e) if e.status_code() == StatusCode::NOT_FOUND => {}
+            Err(e) => return e.error_response(),
+        }
+    }
+    if report.tags.len() > 1 && !query.force.unwrap_or(false) {
+        return HttpResponse::Conflict()
+            .body(serde_json::to_string(&report).expect("Failed to serialize response"));
+    }
+    match registry
+        .send(
+            Method::DELETE,
+            &format!("/{}/manifests/{}", image, report.digest),
+            None,
         )
-        .finish()
-        .get(url)
-        .send()
         .await
     {
+        Ok(res) if res.status.is_success() => {}
+        Ok(res) => return Error::upstream("Failed to delete manifest", &res).error_response(),
         Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Failed to request manifest: {}", e))
+            return Error::unreachable(format!("Failed to delete manifest: {}", e)).error_response()
         }
-        Ok(mut manifest) => match manifest.body().await {
-            Ok(tags) => HttpResponse::Ok().body(format!(
-                "{}",
-                std::str::from_utf8(&tags)
-                    .expect("Failed to parse manifest")
-                    .to_string()
-            )),
-            Err(e) => {
-                HttpResponse::InternalServerError().body(format!("Failed to parse tags: {}", e))
+    }
+    let store = storage.load();
+    cache::invalidate_tags(&*store, &registry, image).await;
+    cache::invalidate_manifest(&*store, &registry, image, &report.digest).await;
+    if let Ok(tags) = req_tags(&registry, image).await {
+        if tags.tags.is_empty() {
+            match uncatalog(&*store, &registry, image).await {
+                Ok(()) => report.removed_from_catalog = true,
+                Err(e) => eprintln!("{}", e),
             }
-        },
+        }
+    }
+    HttpResponse::Ok().body(serde_json::to_string(&report).expect("Failed to serialize response"))
+}
+
+/// refuses requests without the events token as a bearer, for the routes that change
+/// the registry or delete from it
+fn check_token(req: &HttpRequest, config: &Config) -> Result<(), Error> {
+    let header = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok());
+    match events::authorized(config.events.token.as_deref(), header) {
+        true => Ok(()),
+        false => Err(Error::unauthorized("Invalid or unconfigured events token")),
     }
 }
 
+#[post("/events")]
+async fn receive_events(
+    req: HttpRequest,
+    body: Bytes,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    if let Err(e) = check_token(&req, &config.load()) {
+        return e.error_response();
+    }
+    let envelope = match serde_json::from_slice::<EventEnvelope>(&body) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            return Error::bad_request(format!("Failed to parse events: {}", e)).error_response()
+        }
+    };
+    match events::apply(&*storage.load(), &registry, envelope).await {
+        Ok(applied) => HttpResponse::Ok().body(format!("applied {} events", applied)),
+        Err(e) => Error::from(e.context("Failed to apply events")).error_response(),
+    }
+}
+
+#[get("/activity")]
+async fn list_activity(storage: web::Data<Arc<Storage>>, registry: Selected) -> HttpResponse {
+    match events::activity(&*storage.load(), &registry).await {
+        Ok(activity) => HttpResponse::Ok()
+            .body(serde_json::to_string(&activity).expect("Failed to serialize response")),
+        Err(e) => Error::from(e.context("Failed to read activity")).error_response(),
+    }
+}
+
+#[get("/retention/report")]
+async fn retention_report(
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    policies: web::Data<Arc<Live<Vec<Policy>>>>,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    let ttl = config.load().cache.manifests_ttl_secs;
+    match retention::apply(&policies.load(), &*storage.load(), &registry, ttl, true).await {
+        Ok(report) => HttpResponse::Ok()
+            .body(serde_json::to_string(&report).expect("Failed to serialize response")),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+#[post("/retention/execute")]
+async fn retention_execute(
+    req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    registry: Selected,
+    policies: web::Data<Arc<Live<Vec<Policy>>>>,
+    config: web::Data<Arc<Live<Config>>>,
+) -> HttpResponse {
+    if let Err(e) = check_token(&req, &config.load()) {
+        return e.error_response();
+    }
+    let ttl = config.load().cache.manifests_ttl_secs;
+    match retention::apply(&policies.load(), &*storage.load(), &registry, ttl, false).await {
+        Ok(report) => HttpResponse::Ok()
+            .body(serde_json::to_string(&report).expect("Failed to serialize response")),
+        Err(e) => Error::from(e).error_response(),
+    }
+}
+
+fn start_refreshers(
+    storage: &Arc<Storage>,
+    registries: &Registries,
+    interval_secs: u64,
+) -> Refreshers {
+    registries
+        .iter()
+        .map(|registry| {
+            (
+                registry.name().to_string(),
+                Refresher::new(storage.clone(), registry.clone(), interval_secs),
+            )
+        })
+        .collect()
+}
+
+/// rebuilds everything but the listener and CORS origins from the configuration,
+/// nothing changes when the new configuration is invalid
+async fn reload(
+    storage: &Arc<Storage>,
+    config: &Live<Config>,
+    registries: &Live<Registries>,
+    policies: &Live<Vec<Policy>>,
+    refreshers: &Live<Refreshers>,
+) -> Result<(), anyhow::Error> {
+    let new_config = Config::load()?;
+    let new_policies = Policy::from_config(&new_config.retention)?;
+    let current = config.load();
+    // reopening the memory backend would lose everything not in its snapshot
+    let storage_changed = new_config.storage != current.storage
+        || (new_config.storage.backend == StorageBackend::Redis
+            && new_config.redis != current.redis);
+    let new_store = match storage_changed {
+        true => Some(store::open(&new_config)?),
+        false => None,
+    };
+    if new_config.listen != current.listen
+        || new_config.cors != current.cors
+        || new_config.frontend.dist != current.frontend.dist
+    {
+        eprintln!("listen, cors and frontend.dist changes are only applied on restart");
+    }
+    if let Some(new_store) = new_store {
+        storage.store_arc(new_store);
+    }
+    let new_registries = Registries::new(&new_config.registries);
+    let new_refreshers = start_refreshers(
+        storage,
+        &new_registries,
+        new_config.catalog.refresh_interval_secs,
+    );
+    for refresher in refreshers.load().values() {
+        refresher.stop();
+    }
+    for refresher in new_refreshers.values() {
+        refresher.trigger();
+        refresher.schedule();
+    }
+    refreshers.store(new_refreshers);
+    registries.store(new_registries);
+    policies.store(new_policies);
+    config.store(new_config);
+    Ok(())
+}
+
 #[actix_web::main]
 async fn main() -> io::Result<()> {
-    let client = Arc::new(Mutex::new(
-        redis::Client::open(
-            env::var("SHIPYARD_REDIS_URL").unwrap_or("redis://127.0.0.1:6379/".to_string()),
-        )
-        .expect("Failed connecting redis"),
+    let config = Config::load().unwrap_or_else(|e| panic!("{}", e));
+    let storage: Arc<Storage> = Arc::new(Live::from_arc(
+        store::open(&config).unwrap_or_else(|e| panic!("{}", e)),
     ));
-    println!("init catalog...");
-    let lock = client
-        .lock()
-        .expect("Failed to lock redis client at startup");
-    req_list_images(300, lock)
-        .await
-        .expect("Failed to fetch images at startup");
+    let registries = Registries::new(&config.registries);
+    let policies = Arc::new(Live::new(
+        Policy::from_config(&config.retention).expect("Failed to load retention policies"),
+    ));
+    let refreshers = start_refreshers(&storage, &registries, config.catalog.refresh_interval_secs);
+    // the api starts right away, serving the stored catalog until refreshes succeed
+    for (name, refresher) in refreshers.iter() {
+        println!("init catalog of {}...", name);
+        refresher.trigger();
+        refresher.schedule();
+    }
+    let listen = format!("{}:{}", config.listen.address, config.listen.port);
+    let origins = config.cors.origins.clone();
+    let dist = config.frontend.dist.clone();
+    let config = Arc::new(Live::new(config));
+    let registries = Arc::new(Live::new(registries));
+    let refreshers = Arc::new(Live::new(refreshers));
+    {
+        let (storage, config, registries, policies, refreshers) = (
+            storage.clone(),
+            config.clone(),
+            registries.clone(),
+            policies.clone(),
+            refreshers.clone(),
+        );
+        rt::spawn(async move {
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    eprintln!("Failed to listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                println!("reload configuration...");
+                if let Err(e) = reload(&storage, &config, &registries, &policies, &refreshers).await
+                {
+                    eprintln!("Failed to reload configuration: {}", e);
+                }
+            }
+        });
+    }
     println!("start api...");
     HttpServer::new(move || {
+        let cors = match origins.is_empty() {
+            true => Cors::permissive(),
+            false => origins.iter().fold(
+                Cors::default()
+                    .allow_any_method()
+                    .allow_any_header()
+                    .expose_headers(vec!["Docker-Content-Digest"]),
+                |cors, origin| cors.allowed_origin(origin),
+            ),
+        };
         App::new()
-            .app_data(web::Data::new(client.clone()))
-            .wrap(Cors::permissive())
+            .app_data(web::Data::new(storage.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(registries.clone()))
+            .app_data(web::Data::new(policies.clone()))
+            .app_data(web::Data::new(refreshers.clone()))
+            // extractors answer the same json errors as the handlers
+            .app_data(
+                web::PathConfig::default().error_handler(|e, _| {
+                    Error::bad_request(format!("Invalid path: {}", e)).into()
+                }),
+            )
+            .app_data(
+                web::QueryConfig::default().error_handler(|e, _| {
+                    Error::bad_request(format!("Invalid query: {}", e)).into()
+                }),
+            )
+            .default_service(web::route().to(unknown_route))
+            .wrap(cors)
+            .wrap_fn(|req, srv| {
+                // resource patterns, so that paths with images in them do not add series,
+                // handlers sharing a pattern are told apart by the method
+                let route = req
+                    .match_pattern()
+                    .unwrap_or_else(|| "unmatched".to_string());
+                let method = req.method().to_string();
+                let start = Instant::now();
+                let res = srv.call(req);
+                async move {
+                    let res = res.await;
+                    let status = match &res {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    metrics::observe_request(&route, &method, status.as_u16(), start.elapsed());
+                    res
+                }
+            })
+            .service(healthz)
+            .service(readyz)
+            .service(get_metrics)
+            .service(get_openapi)
+            .service(client_config)
             .service(
                 web::scope("/v2")
-                    .service(list_images_page)
-                    .service(refresh_catalog)
-                    .service(list_tags)
-                    .service(get_manifest),
+                    .service(list_registries)
+                    .service(cache_stats)
+                    .service(
+                        web::scope("/{registry}")
+                            .service(list_images_page)
+                            .service(list_images_cursor)
+                            .service(search_images)
+                            .service(refresh_catalog)
+                            .service(refresh_status)
+                            .service(list_tags)
+                            .service(get_manifest)
+                            .service(get_config)
+                            .service(get_size)
+                            .service(compare_images)
+                            .service(delete_manifest)
+                            .service(retention_report)
+                            .service(retention_execute)
+                            .service(receive_events)
+                            .service(list_activity),
+                    ),
             )
+            // after every route, it would answer them otherwise
+            .configure(|cfg| {
+                if let Some(dist) = &dist {
+                    cfg.service(
+                        Files::new("/", dist)
+                            .index_file("index.html")
+                            .default_handler(web::route().to(unknown_route)),
+                    );
+                }
+            })
     })
-    .bind(format!(
-        "{}:{}",
-        env::var("SHIPYARD_URL").unwrap_or("127.0.0.1".to_string()),
-        env::var("SHIPYARD_PORT").unwrap_or("8081".to_string())
-    ))?
+    .bind(listen)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegistryConfig;
+    use crate::store::MemoryStore;
+    use actix_web::{dev::ServiceResponse, test};
+    use shipyard::api::{Endpoint, GetManifest, ListTags};
+
+    fn images(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    /// memory store holding `images` as the catalog of registry `hub`
+    async fn catalog(names: &[&str]) -> Arc<Storage> {
+        let store = MemoryStore::open(None).unwrap();
+        store.replace_catalog("hub", &images(names)).await.unwrap();
+        Arc::new(Live::from_arc(Arc::new(store) as Arc<dyn Store>))
+    }
+
+    /// the catalog routes of registry `hub`, which is never contacted
+    macro_rules! catalog_app {
+        ($storage:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($storage.clone()))
+                    .app_data(web::Data::new(Arc::new(Live::new(Config::default()))))
+                    .app_data(web::Data::new(Arc::new(Live::new(Registries::new(&[
+                        RegistryConfig {
+                            name: "hub".to_string(),
+                            url: "http://127.0.0.1:9/v2".to_string(),
+                            username: None,
+                            password: None,
+                            timeout_secs: 1,
+                        },
+                    ])))))
+                    .service(
+                        web::scope("/v2/{registry}")
+                            .service(list_images_page)
+                            .service(list_images_cursor),
+                    ),
+            )
+            .await
+        };
+    }
+
+    async fn page(res: ServiceResponse) -> CatalogPage {
+        assert!(res.status().is_success(), "{}", res.status());
+        serde_json::from_slice(&test::read_body(res).await).unwrap()
+    }
+
+    fn link(res: &ServiceResponse) -> Option<String> {
+        res.headers()
+            .get(header::LINK)
+            .map(|l| l.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn points_to_the_next_page() {
+        assert_eq!(
+            next_cursor(&images(&["a", "b"]), 0, 5),
+            Some("b".to_string())
+        );
+        assert_eq!(
+            next_cursor(&images(&["c", "d"]), 2, 5),
+            Some("d".to_string())
+        );
+        assert_eq!(next_cursor(&images(&["e"]), 4, 5), None);
+        assert_eq!(next_cursor(&images(&["a", "b"]), 0, 2), None);
+        assert_eq!(next_cursor(&[], 0, 0), None);
+    }
+
+    #[actix_rt::test]
+    async fn pages_by_offset() {
+        let storage = catalog(&["a", "b", "c", "d", "e"]).await;
+        let mut app = catalog_app!(storage);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/1?page_size=2");
+        let first = page(test::call_service(&mut app, req.to_request()).await).await;
+        assert_eq!(first.repositories, ["a", "b"]);
+        assert_eq!((first.page, first.page_size, first.total), (1, 2, 5));
+        assert_eq!(first.next.as_deref(), Some("b"));
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/2?page_size=2");
+        let second = page(test::call_service(&mut app, req.to_request()).await).await;
+        assert_eq!(second.repositories, ["c", "d"]);
+        assert_eq!(second.page, 2);
+        assert_eq!(second.next.as_deref(), Some("d"));
+    }
+
+    #[actix_rt::test]
+    async fn ends_with_a_partial_page() {
+        let storage = catalog(&["a", "b", "c", "d", "e"]).await;
+        let mut app = catalog_app!(storage);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/3?page_size=2");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(link(&res), None);
+        let last = page(res).await;
+        assert_eq!(last.repositories, ["e"]);
+        assert_eq!(last.next, None);
+    }
+
+    #[actix_rt::test]
+    async fn answers_404_past_the_end() {
+        let storage = catalog(&["a", "b", "c"]).await;
+        let mut app = catalog_app!(storage);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/3?page_size=2");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/0");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        // an empty catalog still has a first page
+        let storage = catalog(&[]).await;
+        let mut app = catalog_app!(storage);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/1");
+        let empty = page(test::call_service(&mut app, req.to_request()).await).await;
+        assert!(empty.repositories.is_empty());
+        assert_eq!(empty.next, None);
+    }
+
+    #[actix_rt::test]
+    async fn resumes_from_a_cursor_after_an_insert() {
+        let storage = catalog(&["a", "b", "c", "d"]).await;
+        let mut app = catalog_app!(storage);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog?n=2");
+        let first = page(test::call_service(&mut app, req.to_request()).await).await;
+        assert_eq!(first.repositories, ["a", "b"]);
+        // an offset would now repeat `b`, the cursor does not
+        storage.load().add_to_catalog("hub", "aa").await.unwrap();
+        storage.load().add_to_catalog("hub", "bb").await.unwrap();
+        let uri = format!("/v2/hub/catalog?n=2&last={}", first.next.unwrap());
+        let req = test::TestRequest::get().uri(&uri);
+        let second = page(test::call_service(&mut app, req.to_request()).await).await;
+        assert_eq!(second.repositories, ["bb", "c"]);
+        assert_eq!(second.total, 6);
+        assert_eq!(second.next.as_deref(), Some("c"));
+    }
+
+    #[actix_rt::test]
+    async fn links_the_next_page() {
+        let storage = catalog(&["library/nginx", "team/app", "team/web"]).await;
+        let mut app = catalog_app!(storage);
+        let req = test::TestRequest::get().uri("/v2/hub/catalog/1?page_size=2");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(
+            link(&res).as_deref(),
+            Some("</v2/hub/catalog?n=2&last=team%2Fapp>; rel=\"next\"")
+        );
+        // following it leads to the rest of the catalog
+        let req = test::TestRequest::get().uri("/v2/hub/catalog?n=2&last=team%2Fapp");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(link(&res), None);
+        assert_eq!(page(res).await.repositories, ["team/web"]);
+    }
+
+    #[test]
+    fn splits_image_reference() {
+        let (image, reference) = image_reference("library%2Fnginx:sha256:abc").unwrap();
+        assert_eq!(
+            (image.as_str(), reference.as_str()),
+            ("library/nginx", "sha256:abc")
+        );
+        assert!(image_reference("nginx").is_err());
+    }
+
+    async fn manifest(web::Path((_, image)): web::Path<(String, String)>) -> HttpResponse {
+        let (image, reference) = image_reference(&image).unwrap();
+        HttpResponse::Ok().body(format!("{}@{}", image, reference))
+    }
+
+    async fn tags(web::Path((_, image)): web::Path<(String, String)>) -> HttpResponse {
+        HttpResponse::Ok().body(url_decode(&image))
+    }
+
+    /// the router leaves `%2F` encoded, the handlers decode it
+    #[actix_rt::test]
+    async fn decodes_namespaced_images_from_urls() {
+        let mut app = test::init_service(
+            App::new()
+                .route("/v2/{registry}/manifest/{image}", web::get().to(manifest))
+                .route("/v2/{registry}/tags/{image}", web::get().to(tags)),
+        )
+        .await;
+        let endpoint = GetManifest {
+            registry: "hub".to_string(),
+            image: "team/app/worker".to_string(),
+            reference: "sha256:0123".to_string(),
+        };
+        let req = test::TestRequest::get().uri(&endpoint.url()).to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "team/app/worker@sha256:0123");
+        let endpoint = ListTags {
+            registry: "hub".to_string(),
+            image: "library/nginx".to_string(),
+        };
+        let req = test::TestRequest::get().uri(&endpoint.url()).to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "library/nginx");
+    }
+
+    /// `1.0` and `latest` share a digest, `gone` was untagged since the tags were listed
+    async fn upstream_tags() -> HttpResponse {
+        HttpResponse::Ok().json(Tags {
+            name: "app".to_string(),
+            tags: images(&["1.0", "2.0", "gone", "latest"]),
+        })
+    }
+
+    async fn upstream_manifest(web::Path(reference): web::Path<String>) -> HttpResponse {
+        let digest = match reference.as_str() {
+            "1.0" | "latest" | "sha256:aaa" => "sha256:aaa",
+            "2.0" => "sha256:bbb",
+            _ => return HttpResponse::NotFound().finish(),
+        };
+        HttpResponse::Ok()
+            .header("Docker-Content-Digest", digest)
+            .finish()
+    }
+
+    async fn upstream_delete() -> HttpResponse {
+        HttpResponse::Accepted().finish()
+    }
+
+    async fn report(res: ServiceResponse) -> DeleteReport {
+        serde_json::from_slice(&test::read_body(res).await).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn deletes_every_tag_of_a_digest() {
+        let srv = test::start(|| {
+            App::new()
+                .route("/v2/app/tags/list", web::get().to(upstream_tags))
+                .route(
+                    "/v2/app/manifests/{reference}",
+                    web::head().to(upstream_manifest),
+                )
+                .route(
+                    "/v2/app/manifests/{reference}",
+                    web::delete().to(upstream_delete),
+                )
+        });
+        let mut config = Config::default();
+        config.events.token = Some("tok".to_string());
+        let storage = catalog(&["app"]).await;
+        storage
+            .load()
+            .set_manifest("hub", "app", "sha256:aaa", "{}", 60)
+            .await
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(storage.clone()))
+                .app_data(web::Data::new(Arc::new(Live::new(config))))
+                .app_data(web::Data::new(Arc::new(Live::new(Registries::new(&[
+                    RegistryConfig {
+                        name: "hub".to_string(),
+                        url: srv.url("/v2"),
+                        username: None,
+                        password: None,
+                        timeout_secs: 5,
+                    },
+                ])))))
+                .service(web::scope("/v2/{registry}").service(delete_manifest)),
+        )
+        .await;
+        let req = test::TestRequest::delete().uri("/v2/hub/manifest/app:1.0");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        // the tag that is gone does not stop the deletion
+        let req = test::TestRequest::delete()
+            .uri("/v2/hub/manifest/app:1.0")
+            .header("Authorization", "Bearer tok");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let shared = report(res).await;
+        assert_eq!(shared.digest, "sha256:aaa");
+        assert_eq!(shared.tags, ["1.0", "latest"]);
+        let req = test::TestRequest::delete()
+            .uri("/v2/hub/manifest/app:1.0?force=true")
+            .header("Authorization", "Bearer tok");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let deleted = report(res).await;
+        assert_eq!(deleted.tags, ["1.0", "latest"]);
+        assert!(!deleted.removed_from_catalog);
+        let cached = storage.load().manifest("hub", "app", "sha256:aaa").await;
+        assert_eq!(cached.unwrap(), None);
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
+
+use crate::{cache, refresh::Refreshers, registry::Registries, store::Store};
+
+/// upper bounds of the latency histograms, in seconds
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// values of a metric by rendered label set
+struct Family<T>(Mutex<BTreeMap<String, T>>);
+
+impl<T: Default> Family<T> {
+    const fn new() -> Family<T> {
+        Family(Mutex::new(BTreeMap::new()))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, T>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn update(&self, labels: String, f: impl FnOnce(&mut T)) {
+        f(self.lock().entry(labels).or_default())
+    }
+}
+
+static HTTP_REQUESTS: Family<u64> = Family::new();
+static HTTP_DURATION: Family<Histogram> = Family::new();
+static REGISTRY_REQUESTS: Family<u64> = Family::new();
+static REGISTRY_DURATION: Family<Histogram> = Family::new();
+static REDIS_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// renders a label set, values escaped as the text format requires
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', r"\\")
+                .replace('"', r#"\""#)
+                .replace('\n', r"\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// records a request to the api, `route` being the pattern of its resource
+pub fn observe_request(route: &str, method: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS.update(
+        labels(&[
+            ("route", route),
+            ("method", method),
+            ("status", &status.to_string()),
+        ]),
+        |count| *count += 1,
+    );
+    HTTP_DURATION.update(labels(&[("route", route), ("method", method)]), |h| {
+        h.observe(elapsed.as_secs_f64())
+    });
+}
+
+/// records a request to a registry, `status` is `None` when no response was received
+pub fn observe_registry(registry: &str, method: &str, status: Option<u16>, elapsed: Duration) {
+    let status = status.map_or("error".to_string(), |s| s.to_string());
+    REGISTRY_REQUESTS.update(
+        labels(&[
+            ("registry", registry),
+            ("method", method),
+            ("status", &status),
+        ]),
+        |count| *count += 1,
+    );
+    REGISTRY_DURATION.update(labels(&[("registry", registry), ("method", method)]), |h| {
+        h.observe(elapsed.as_secs_f64())
+    });
+}
+
+pub fn redis_error() {
+    REDIS_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn render_counters(out: &mut String, name: &str, help: &str, family: &Family<u64>) {
+    header(out, name, "counter", help);
+    for (labels, count) in family.lock().iter() {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, count);
+    }
+}
+
+fn render_histograms(out: &mut String, name: &str, help: &str, family: &Family<Histogram>) {
+    header(out, name, "histogram", help);
+    for (labels, h) in family.lock().iter() {
+        for (count, bound) in h.buckets.iter().zip(BUCKETS.iter()) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, count
+            );
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, h.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, h.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, h.count);
+    }
+}
+
+/// every metric in the Prometheus text format, catalog sizes are read from the store
+pub async fn render(store: &dyn Store, registries: &Registries, refreshers: &Refreshers) -> String {
+    let mut out = String::new();
+    render_counters(
+        &mut out,
+        "shipyard_http_requests_total",
+        "Requests to the api by route, method and status.",
+        &HTTP_REQUESTS,
+    );
+    render_histograms(
+        &mut out,
+        "shipyard_http_request_duration_seconds",
+        "Time spent answering requests to the api by route and method.",
+        &HTTP_DURATION,
+    );
+    render_counters(
+        &mut out,
+        "shipyard_registry_requests_total",
+        "Requests to the registries by method and status, error when none was received.",
+        &REGISTRY_REQUESTS,
+    );
+    render_histograms(
+        &mut out,
+        "shipyard_registry_request_duration_seconds",
+        "Time spent waiting for the registries by method.",
+        &REGISTRY_DURATION,
+    );
+    header(
+        &mut out,
+        "shipyard_redis_errors_total",
+        "counter",
+        "Failed redis commands.",
+    );
+    let _ = writeln!(
+        out,
+        "shipyard_redis_errors_total {}",
+        REDIS_ERRORS.load(Ordering::Relaxed)
+    );
+
+    let stats = cache::stats();
+    let caches = [("tags", &stats.tags), ("manifests", &stats.manifests)];
+    header(
+        &mut out,
+        "shipyard_cache_hits_total",
+        "counter",
+        "Lookups answered by a cache.",
+    );
+    for (cache, counts) in caches.iter() {
+        let _ = writeln!(
+            out,
+            "shipyard_cache_hits_total{{cache=\"{}\"}} {}",
+            cache, counts.hits
+        );
+    }
+    header(
+        &mut out,
+        "shipyard_cache_misses_total",
+        "counter",
+        "Lookups that had to ask the registry.",
+    );
+    for (cache, counts) in caches.iter() {
+        let _ = writeln!(
+            out,
+            "shipyard_cache_misses_total{{cache=\"{}\"}} {}",
+            cache, counts.misses
+        );
+    }
+    header(
+        &mut out,
+        "shipyard_cache_hit_ratio",
+        "gauge",
+        "Share of lookups answered by a cache since startup.",
+    );
+    for (cache, counts) in caches.iter() {
+        let lookups = counts.hits + counts.misses;
+        let ratio = match lookups {
+            0 => 0.0,
+            _ => counts.hits as f64 / lookups as f64,
+        };
+        let _ = writeln!(
+            out,
+            "shipyard_cache_hit_ratio{{cache=\"{}\"}} {}",
+            cache, ratio
+        );
+    }
+
+    header(
+        &mut out,
+        "shipyard_catalog_images",
+        "gauge",
+        "Images in the catalog of each registry.",
+    );
+    for registry in registries.iter() {
+        match store.catalog_len(registry.name()).await {
+            Ok(total) => {
+                let _ = writeln!(
+                    out,
+                    "shipyard_catalog_images{{{}}} {}",
+                    labels(&[("registry", registry.name())]),
+                    total
+                );
+            }
+            Err(e) => eprintln!("Failed to read catalog size of {}: {}", registry.name(), e),
+        }
+    }
+    let statuses: Vec<_> = refreshers
+        .iter()
+        .map(|(name, refresher)| (labels(&[("registry", name)]), refresher.status()))
+        .collect();
+    header(
+        &mut out,
+        "shipyard_catalog_last_refresh_timestamp_seconds",
+        "gauge",
+        "End of the last finished catalog refresh, unix seconds.",
+    );
+    for (labels, status) in statuses.iter() {
+        if let Some(finished) = status.last_finished {
+            let _ = writeln!(
+                out,
+                "shipyard_catalog_last_refresh_timestamp_seconds{{{}}} {}",
+                labels, finished
+            );
+        }
+    }
+    header(
+        &mut out,
+        "shipyard_catalog_last_refresh_duration_seconds",
+        "gauge",
+        "Duration of the last finished catalog refresh.",
+    );
+    for (labels, status) in statuses.iter() {
+        if let Some(ms) = status.last_duration_ms {
+            let _ = writeln!(
+                out,
+                "shipyard_catalog_last_refresh_duration_seconds{{{}}} {}",
+                labels,
+                ms as f64 / 1000.0
+            );
+        }
+    }
+    header(
+        &mut out,
+        "shipyard_catalog_last_refresh_success",
+        "gauge",
+        "1 when the last finished catalog refresh succeeded.",
+    );
+    for (labels, status) in statuses.iter() {
+        if status.last_finished.is_some() {
+            let _ = writeln!(
+                out,
+                "shipyard_catalog_last_refresh_success{{{}}} {}",
+                labels,
+                status.last_error.is_none() as u8
+            );
+        }
+    }
+    out
+}
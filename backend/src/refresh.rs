@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::rt;
+use shipyard::RefreshStatus;
+
+use crate::{registry::Registry, req_list_images, Storage, REGISTRY_PAGE_SIZE};
+
+/// seconds between checks for a due refresh, failed refreshes are retried as often
+const RETRY_SECS: u64 = 30;
+
+/// refresher of each registry, by name
+pub type Refreshers = HashMap<String, Arc<Refresher>>;
+
+/// runs catalog refreshes in the background, one at a time
+pub struct Refresher {
+    storage: Arc<Storage>,
+    registry: Arc<Registry>,
+    status: Mutex<RefreshStatus>,
+    stopped: AtomicBool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Refresher {
+    pub fn new(
+        storage: Arc<Storage>,
+        registry: Arc<Registry>,
+        interval_secs: u64,
+    ) -> Arc<Refresher> {
+        Arc::new(Refresher {
+            storage,
+            registry,
+            status: Mutex::new(RefreshStatus {
+                interval_secs,
+                ..RefreshStatus::default()
+            }),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RefreshStatus> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn status(&self) -> RefreshStatus {
+        self.lock().clone()
+    }
+
+    /// marks a new job as running, or returns the id of the one already running
+    fn begin(&self) -> Result<u64, u64> {
+        let mut status = self.lock();
+        if status.running {
+            return Err(status.job.unwrap_or_default());
+        }
+        let job = status.job.map_or(1, |job| job + 1);
+        status.running = true;
+        status.job = Some(job);
+        status.last_started = Some(now());
+        Ok(job)
+    }
+
+    async fn run(&self) -> Result<usize, anyhow::Error> {
+        let start = Instant::now();
+        let res = req_list_images(REGISTRY_PAGE_SIZE, &self.storage, &self.registry).await;
+        let mut status = self.lock();
+        status.running = false;
+        status.last_finished = Some(now());
+        status.last_duration_ms = Some(start.elapsed().as_millis() as u64);
+        match &res {
+            Ok(count) => {
+                status.last_count = Some(*count);
+                status.last_error = None;
+            }
+            Err(e) => {
+                eprintln!("Failed to refresh catalog: {}", e);
+                status.last_error = Some(e.to_string());
+            }
+        }
+        res
+    }
+
+    /// starts a refresh in the background unless one is running, returns the job id
+    pub fn trigger(self: &Arc<Self>) -> u64 {
+        match self.begin() {
+            Err(job) => job,
+            Ok(job) => {
+                let refresher = self.clone();
+                rt::spawn(async move {
+                    let _ = refresher.run().await;
+                });
+                job
+            }
+        }
+    }
+
+    /// ends the scheduled refreshes, a running one still completes
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// whether the last refresh failed, or started more than `interval_secs` ago
+    fn due(&self, interval_secs: u64) -> bool {
+        let status = self.lock();
+        match (status.running, status.last_started, &status.last_error) {
+            (true, _, _) => false,
+            (false, None, _) | (false, _, Some(_)) => true,
+            (false, Some(started), None) => interval_secs > 0 && now() >= started + interval_secs,
+        }
+    }
+
+    /// triggers a refresh every `interval_secs` until stopped, and retries failed refreshes
+    /// every `RETRY_SECS`, so that a registry down at startup is picked up once back
+    ///
+    /// with an interval of 0 only failed refreshes are retried
+    pub fn schedule(self: &Arc<Self>) {
+        let interval = self.lock().interval_secs;
+        let period = Duration::from_secs(match interval {
+            0 => RETRY_SECS,
+            interval => interval.min(RETRY_SECS),
+        });
+        let refresher = self.clone();
+        rt::spawn(async move {
+            let mut ticks = rt::time::interval_at(rt::time::Instant::now() + period, period);
+            loop {
+                ticks.tick().await;
+                if refresher.stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                if refresher.due(interval) {
+                    refresher.trigger();
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,727 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    client::{ClientBuilder, ClientRequest},
+    dev::Payload,
+    http::{header, HeaderMap, Method, StatusCode},
+    rt,
+    web::{self, Bytes},
+    FromRequest, HttpRequest,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::{Live, RegistryConfig},
+    error::Error,
+    metrics,
+};
+
+const BODY_LIMIT: usize = 8 * 1024 * 1024;
+
+/// username and password used against the registry or its token server
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Clone)]
+enum Auth {
+    Basic,
+    Bearer(String),
+}
+
+#[derive(PartialEq, Debug)]
+enum Challenge {
+    Basic,
+    Bearer {
+        realm: String,
+        service: Option<String>,
+        scope: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+/// fully read response from the registry
+pub struct RegistryResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// client for a docker registry, handling Basic and Bearer token authentication
+pub struct Registry {
+    name: String,
+    url: String,
+    credentials: Option<Credentials>,
+    timeout: Duration,
+    // auth to use per scope, with the instant it stops being valid, requests without a
+    // scope like pings are under the empty one
+    auth: Mutex<HashMap<String, (Auth, Option<Instant>)>>,
+    // the registry asked for Basic once, credentials are then sent up front
+    basic: AtomicBool,
+    // last time the registry answered without a server error
+    last_seen: Mutex<Option<Instant>>,
+    // a background ping is running
+    pinging: AtomicBool,
+    // why the last background ping failed
+    ping_error: Mutex<Option<String>>,
+}
+
+impl Registry {
+    pub fn new(config: &RegistryConfig) -> Registry {
+        let credentials = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => Some(Credentials {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        };
+        Registry {
+            name: config.name.clone(),
+            url: config.url.clone(),
+            credentials,
+            timeout: Duration::from_secs(config.timeout_secs),
+            auth: Mutex::new(HashMap::new()),
+            basic: AtomicBool::new(false),
+            last_seen: Mutex::new(None),
+            pinging: AtomicBool::new(false),
+            ping_error: Mutex::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// time elapsed since the registry last answered, `None` when it never did
+    pub fn last_seen(&self) -> Option<Duration> {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .map(|seen| seen.elapsed())
+    }
+
+    /// checks that the api answers at `{url}/`
+    pub async fn ping(&self) -> Result<(), anyhow::Error> {
+        match self.get("/", None).await {
+            Ok(res) if res.status.is_success() => Ok(()),
+            Ok(res) => Err(anyhow::Error::msg(format!(
+                "Registry answered {}",
+                res.status
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// pings in the background unless a ping is already running, for callers which must
+    /// answer right away and read `last_seen` and `ping_error` instead
+    pub fn ping_later(self: &Arc<Self>) {
+        if self.pinging.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let registry = self.clone();
+        rt::spawn(async move {
+            let error = registry.ping().await.err().map(|e| e.to_string());
+            *registry
+                .ping_error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = error;
+            registry.pinging.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// why the last background ping failed, `None` when it succeeded or none ran
+    pub fn ping_error(&self) -> Option<String> {
+        self.ping_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub async fn get(
+        &self,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Result<RegistryResponse, anyhow::Error> {
+        self.send(Method::GET, path, accept).await
+    }
+
+    /// sends a request to `{url}{path}`, authenticating and retrying once on 401
+    pub async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Result<RegistryResponse, anyhow::Error> {
+        let scope = scope_for(&method, path);
+        let auth = self.cached_auth(&scope);
+        let res = self
+            .request(method.clone(), path, accept, auth.as_ref())
+            .await?;
+        if res.status != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        let challenge = match res
+            .headers
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_challenge)
+        {
+            Some(challenge) => challenge,
+            None => return Ok(res),
+        };
+        let (auth, expires) = match challenge {
+            Challenge::Basic => match self.credentials {
+                Some(_) => {
+                    self.basic.store(true, Ordering::Relaxed);
+                    (Auth::Basic, None)
+                }
+                None => return Ok(res),
+            },
+            Challenge::Bearer {
+                realm,
+                service,
+                scope: challenge_scope,
+            } => {
+                let (token, expires) = self
+                    .fetch_token(
+                        &realm,
+                        service.as_deref(),
+                        challenge_scope.as_deref().or(scope.as_deref()),
+                    )
+                    .await?;
+                (Auth::Bearer(token), Some(expires))
+            }
+        };
+        if let Ok(mut cache) = self.auth.lock() {
+            cache.insert(scope.unwrap_or_default(), (auth.clone(), expires));
+        }
+        self.request(method, path, accept, Some(&auth)).await
+    }
+
+    fn cached_auth(&self, scope: &Option<String>) -> Option<Auth> {
+        let scope = scope.as_deref().unwrap_or_default();
+        let mut cache = self.auth.lock().ok()?;
+        match cache.get(scope) {
+            Some((_, Some(expires))) if *expires <= Instant::now() => {
+                cache.remove(scope);
+                None
+            }
+            Some((auth, _)) => Some(auth.clone()),
+            None if self.basic.load(Ordering::Relaxed) => Some(Auth::Basic),
+            None => None,
+        }
+    }
+
+    fn authorize(&self, req: ClientRequest, auth: Option<&Auth>) -> ClientRequest {
+        match (auth, &self.credentials) {
+            (Some(Auth::Bearer(token)), _) => req.bearer_auth(token),
+            (Some(Auth::Basic), Some(c)) => req.basic_auth(&c.username, Some(&c.password)),
+            _ => req,
+        }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        accept: Option<&str>,
+        auth: Option<&Auth>,
+    ) -> Result<RegistryResponse, anyhow::Error> {
+        let start = Instant::now();
+        let method_name = method.to_string();
+        let res = self.exchange(method, path, accept, auth).await;
+        if res.as_ref().is_ok_and(|r| !r.status.is_server_error()) {
+            *self
+                .last_seen
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+        }
+        metrics::observe_registry(
+            &self.name,
+            &method_name,
+            res.as_ref().ok().map(|r| r.status.as_u16()),
+            start.elapsed(),
+        );
+        res
+    }
+
+    async fn exchange(
+        &self,
+        method: Method,
+        path: &str,
+        accept: Option<&str>,
+        auth: Option<&Auth>,
+    ) -> Result<RegistryResponse, anyhow::Error> {
+        let client = ClientBuilder::new().timeout(self.timeout).finish();
+        let mut req = client.request(method.clone(), format!("{}{}", self.url, path));
+        if let Some(accept) = accept {
+            req = req.header(header::ACCEPT, accept);
+        }
+        let mut res = match self.authorize(req, auth).send().await {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to request registry: {}",
+                    e
+                )))
+            }
+        };
+        // blobs are often redirected to object storage, which must not receive
+        // the registry credentials and which awc does not follow on its own, the method
+        // is kept so that `HEAD` and `DELETE` stay what they are
+        for _ in 0..3 {
+            if !res.status().is_redirection() {
+                break;
+            }
+            let location = match res
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+            {
+                Some(location) => location.to_string(),
+                None => return Err(anyhow::Error::msg("Registry redirect without location")),
+            };
+            res = match client.request(method.clone(), location).send().await {
+                Ok(res) => res,
+                Err(e) => {
+                    return Err(anyhow::Error::msg(format!(
+                        "Failed to follow registry redirect: {}",
+                        e
+                    )))
+                }
+            };
+        }
+        match res.body().limit(BODY_LIMIT).await {
+            Ok(body) => Ok(RegistryResponse {
+                status: res.status(),
+                headers: res.headers().clone(),
+                body,
+            }),
+            Err(e) => Err(anyhow::Error::msg(format!(
+                "Failed to read registry response: {}",
+                e
+            ))),
+        }
+    }
+
+    async fn fetch_token(
+        &self,
+        realm: &str,
+        service: Option<&str>,
+        scope: Option<&str>,
+    ) -> Result<(String, Instant), anyhow::Error> {
+        let mut query = vec![];
+        if let Some(service) = service {
+            query.push(("service", service));
+        }
+        if let Some(scope) = scope {
+            query.push(("scope", scope));
+        }
+        let req = match ClientBuilder::new()
+            .timeout(self.timeout)
+            .finish()
+            .get(realm)
+            .query(&query)
+        {
+            Ok(req) => req,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to build token request: {}",
+                    e
+                )))
+            }
+        };
+        let req = match &self.credentials {
+            Some(c) => req.basic_auth(&c.username, Some(&c.password)),
+            None => req,
+        };
+        let mut res = match req.send().await {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to request token: {}",
+                    e
+                )))
+            }
+        };
+        if !res.status().is_success() {
+            return Err(anyhow::Error::msg(format!(
+                "Token server answered {}",
+                res.status()
+            )));
+        }
+        match res.json::<TokenResponse>().await {
+            Ok(TokenResponse {
+                token,
+                access_token,
+                expires_in,
+            }) => match token.or(access_token) {
+                // renew a little before the registry starts rejecting the token
+                Some(token) => Ok((
+                    token,
+                    Instant::now() + Duration::from_secs(expires_in.unwrap_or(60).max(20) - 10),
+                )),
+                None => Err(anyhow::Error::msg("Token server response has no token")),
+            },
+            Err(e) => Err(anyhow::Error::msg(format!(
+                "Failed to parse token response: {}",
+                e
+            ))),
+        }
+    }
+}
+
+/// registries served by the backend, in configuration order
+pub struct Registries(Vec<Arc<Registry>>);
+
+impl Registries {
+    pub fn new(configs: &[RegistryConfig]) -> Registries {
+        Registries(configs.iter().map(|c| Arc::new(Registry::new(c))).collect())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Registry>> {
+        self.0.iter().find(|r| r.name == name).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Registry>> {
+        self.0.iter()
+    }
+}
+
+/// registry named by the `{registry}` segment of the request path
+pub struct Selected(Arc<Registry>);
+
+impl Deref for Selected {
+    type Target = Arc<Registry>;
+
+    fn deref(&self) -> &Arc<Registry> {
+        &self.0
+    }
+}
+
+impl FromRequest for Selected {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Selected, actix_web::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let name = req.match_info().get("registry").unwrap_or_default();
+        ready(
+            match req
+                .app_data::<web::Data<Arc<Live<Registries>>>>()
+                .and_then(|registries| registries.load().get(name))
+            {
+                Some(registry) => Ok(Selected(registry)),
+                None => Err(Error::not_found(format!("Unknown registry {}", name)).into()),
+            },
+        )
+    }
+}
+
+/// token scope needed for a registry API path
+fn scope_for(method: &Method, path: &str) -> Option<String> {
+    if path.starts_with("/_catalog") {
+        return Some("registry:catalog:*".to_string());
+    }
+    let path = path.trim_start_matches('/');
+    let name = ["/manifests/", "/tags/", "/blobs/"]
+        .iter()
+        .filter_map(|s| path.rfind(s))
+        .max()
+        .map(|i| &path[..i])?;
+    let actions = match *method {
+        Method::DELETE => "delete",
+        Method::GET | Method::HEAD => "pull",
+        _ => "pull,push",
+    };
+    Some(format!("repository:{}:{}", name, actions))
+}
+
+/// parses a `WWW-Authenticate` header value
+fn parse_challenge(header: &str) -> Option<Challenge> {
+    let (scheme, params) = match header.trim().split_once(' ') {
+        Some((scheme, params)) => (scheme, params),
+        None => (header.trim(), ""),
+    };
+    if scheme.eq_ignore_ascii_case("basic") {
+        return Some(Challenge::Basic);
+    }
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let mut values = HashMap::new();
+    let mut chars = params.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() {
+            break;
+        }
+        let value: String = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            value
+        } else {
+            chars.by_ref().take_while(|c| *c != ',').collect()
+        };
+        values.insert(key.trim().to_lowercase(), value);
+    }
+    Some(Challenge::Bearer {
+        realm: values.remove("realm")?,
+        service: values.remove("service"),
+        scope: values.remove("scope"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Counters {
+        challenges: AtomicUsize,
+        tokens: AtomicUsize,
+    }
+
+    fn registry(url: String, credentials: bool) -> Registry {
+        Registry::new(&RegistryConfig {
+            name: "test".to_string(),
+            url,
+            username: credentials.then(|| "user".to_string()),
+            password: credentials.then(|| "secret".to_string()),
+            timeout_secs: 5,
+        })
+    }
+
+    fn authorization(req: &HttpRequest) -> &str {
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    /// answers with a Bearer challenge until the token from `/token` is presented
+    async fn bearer(req: HttpRequest, counters: web::Data<Counters>) -> HttpResponse {
+        if authorization(&req) == "Bearer tok" {
+            return HttpResponse::Ok().finish();
+        }
+        counters.challenges.fetch_add(1, Ordering::SeqCst);
+        let realm = format!("http://{}/token", req.connection_info().host());
+        HttpResponse::Unauthorized()
+            .header(
+                header::WWW_AUTHENTICATE,
+                format!("Bearer realm=\"{}\",service=\"test\"", realm),
+            )
+            .finish()
+    }
+
+    async fn token(counters: web::Data<Counters>) -> HttpResponse {
+        counters.tokens.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok().json(serde_json::json!({"token": "tok", "expires_in": 300}))
+    }
+
+    /// answers with a Basic challenge until credentials are presented
+    async fn basic(req: HttpRequest, counters: web::Data<Counters>) -> HttpResponse {
+        if authorization(&req).starts_with("Basic ") {
+            return HttpResponse::Ok().finish();
+        }
+        counters.challenges.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, "Basic realm=\"test\"")
+            .finish()
+    }
+
+    async fn redirect(req: HttpRequest) -> HttpResponse {
+        HttpResponse::TemporaryRedirect()
+            .header(
+                header::LOCATION,
+                format!("http://{}/storage", req.connection_info().host()),
+            )
+            .finish()
+    }
+
+    /// echoes the method in a header, since HEAD responses have no body
+    async fn storage(req: HttpRequest) -> HttpResponse {
+        HttpResponse::Ok()
+            .header("x-method", req.method().as_str())
+            .finish()
+    }
+
+    fn server(counters: Arc<Counters>) -> test::TestServer {
+        test::start(move || {
+            App::new()
+                .app_data(web::Data::from(counters.clone()))
+                .route("/token", web::get().to(token))
+                .route("/storage", web::route().to(storage))
+                .route("/v2/", web::get().to(bearer))
+                .route("/v2/a/manifests/{reference}", web::get().to(bearer))
+                .route("/v2/b/manifests/{reference}", web::get().to(basic))
+                .route("/v2/c/blobs/{digest}", web::route().to(redirect))
+        })
+    }
+
+    #[test]
+    fn parses_bearer_challenges() {
+        assert_eq!(
+            parse_challenge(
+                r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:team/app:pull,push""#
+            ),
+            Some(Challenge::Bearer {
+                realm: "https://auth.example.com/token".to_string(),
+                service: Some("registry.example.com".to_string()),
+                scope: Some("repository:team/app:pull,push".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_challenge(r#"bearer service=registry, realm="https://a/\"t\"" ,error="x""#),
+            Some(Challenge::Bearer {
+                realm: r#"https://a/"t""#.to_string(),
+                service: Some("registry".to_string()),
+                scope: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_other_challenges() {
+        assert_eq!(
+            parse_challenge(r#"Basic realm="Registry Realm""#),
+            Some(Challenge::Basic)
+        );
+        assert_eq!(parse_challenge("Basic"), Some(Challenge::Basic));
+        assert_eq!(parse_challenge(r#"Bearer service="registry""#), None);
+        assert_eq!(parse_challenge(r#"Negotiate realm="x""#), None);
+    }
+
+    #[test]
+    fn scopes_paths() {
+        assert_eq!(
+            scope_for(&Method::GET, "/_catalog?n=10"),
+            Some("registry:catalog:*".to_string())
+        );
+        assert_eq!(
+            scope_for(&Method::HEAD, "/team/app/manifests/latest"),
+            Some("repository:team/app:pull".to_string())
+        );
+        assert_eq!(
+            scope_for(&Method::DELETE, "/app/manifests/sha256:abc"),
+            Some("repository:app:delete".to_string())
+        );
+        assert_eq!(
+            scope_for(&Method::PUT, "/app/blobs/uploads/"),
+            Some("repository:app:pull,push".to_string())
+        );
+        assert_eq!(scope_for(&Method::GET, "/"), None);
+    }
+
+    #[actix_rt::test]
+    async fn retries_with_a_bearer_token() {
+        let counters = Arc::new(Counters::default());
+        let srv = server(counters.clone());
+        let registry = registry(srv.url("/v2"), false);
+        for _ in 0..2 {
+            let res = registry.get("/a/manifests/latest", None).await.unwrap();
+            assert_eq!(res.status, StatusCode::OK);
+        }
+        assert_eq!(counters.tokens.load(Ordering::SeqCst), 1);
+        assert_eq!(counters.challenges.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn caches_the_ping_token() {
+        let counters = Arc::new(Counters::default());
+        let srv = server(counters.clone());
+        let registry = registry(srv.url("/v2"), false);
+        registry.ping().await.unwrap();
+        registry.ping().await.unwrap();
+        assert_eq!(counters.tokens.load(Ordering::SeqCst), 1);
+    }
+
+    /// waits for the background ping started by `ping_later`
+    async fn pinged(registry: &Registry) {
+        for _ in 0..100 {
+            if !registry.pinging.load(Ordering::SeqCst) {
+                return;
+            }
+            rt::time::delay_for(Duration::from_millis(20)).await;
+        }
+        panic!("ping did not finish");
+    }
+
+    #[actix_rt::test]
+    async fn pings_in_the_background() {
+        let counters = Arc::new(Counters::default());
+        let srv = server(counters.clone());
+        let reachable = Arc::new(registry(srv.url("/v2"), false));
+        reachable.ping_later();
+        reachable.ping_later();
+        assert!(reachable.last_seen().is_none());
+        pinged(&reachable).await;
+        assert!(reachable.last_seen().is_some());
+        assert_eq!(reachable.ping_error(), None);
+        assert_eq!(counters.challenges.load(Ordering::SeqCst), 1);
+
+        let unreachable = Arc::new(registry("http://127.0.0.1:9/v2".to_string(), false));
+        unreachable.ping_later();
+        pinged(&unreachable).await;
+        assert!(unreachable.last_seen().is_none());
+        assert!(unreachable.ping_error().is_some());
+    }
+
+    #[actix_rt::test]
+    async fn sends_basic_credentials_up_front() {
+        let counters = Arc::new(Counters::default());
+        let srv = server(counters.clone());
+        let registry = registry(srv.url("/v2"), true);
+        for reference in &["1", "2", "3"] {
+            let path = format!("/b/manifests/{}", reference);
+            let res = registry.get(&path, None).await.unwrap();
+            assert_eq!(res.status, StatusCode::OK);
+        }
+        assert_eq!(counters.challenges.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn follows_redirects_with_the_same_method() {
+        let srv = server(Arc::new(Counters::default()));
+        let registry = registry(srv.url("/v2"), false);
+        for method in &[Method::GET, Method::HEAD, Method::DELETE] {
+            let res = registry
+                .send(method.clone(), "/c/blobs/sha256:abc", None)
+                .await
+                .unwrap();
+            assert_eq!(res.status, StatusCode::OK);
+            assert_eq!(res.headers.get("x-method").unwrap(), method.as_str());
+        }
+    }
+}
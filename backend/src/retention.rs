@@ -0,0 +1,415 @@
+use std::{
+    collections::HashSet,
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::http::Method;
+use regex::Regex;
+use serde::Deserialize;
+use shipyard::{RepositoryRetention, RetentionReport, TagRemoval};
+
+use crate::{
+    cache::{invalidate_manifest, invalidate_tags},
+    config::RetentionConfig,
+    registry::Registry,
+    req_digest, req_image_config, req_image_size, req_tags,
+    store::Store,
+    uncatalog,
+};
+
+#[derive(Deserialize)]
+struct PolicyConfig {
+    registry: Option<String>,
+    repository: String,
+    keep_last: Option<usize>,
+    #[serde(default)]
+    keep_tags: Vec<String>,
+    older_than_days: Option<u64>,
+}
+
+/// retention rules for the repositories matching a glob, in one registry or all of them
+///
+/// a tag is deleted unless it matches one of `keep_tags`, is among the `keep_last`
+/// most recent tags or is younger than `older_than_days`
+pub struct Policy {
+    registry: Option<String>,
+    repository: String,
+    repository_re: Regex,
+    keep_last: Option<usize>,
+    keep_tags: Vec<Regex>,
+    older_than_days: Option<u64>,
+}
+
+impl Policy {
+    /// loads policies from the configured json file, none when unset
+    pub fn from_config(config: &RetentionConfig) -> Result<Vec<Policy>, anyhow::Error> {
+        match &config.file {
+            Some(path) => Policy::load(path),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Vec<Policy>, anyhow::Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to read retention policies {}: {}",
+                    path, e
+                )))
+            }
+        };
+        let configs: Vec<PolicyConfig> = match serde_json::from_str(&content) {
+            Ok(configs) => configs,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Failed to parse retention policies {}: {}",
+                    path, e
+                )))
+            }
+        };
+        configs.into_iter().map(Policy::compile).collect()
+    }
+
+    fn compile(config: PolicyConfig) -> Result<Policy, anyhow::Error> {
+        if config.keep_last.is_none() && config.older_than_days.is_none() {
+            return Err(anyhow::Error::msg(format!(
+                "Retention policy for {} must set keep_last or older_than_days",
+                config.repository
+            )));
+        }
+        let glob = regex::escape(&config.repository)
+            .replace(r"\*", ".*")
+            .replace(r"\?", ".");
+        let repository_re = match Regex::new(&format!("^{}$", glob)) {
+            Ok(re) => re,
+            Err(e) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Invalid repository glob {}: {}",
+                    config.repository, e
+                )))
+            }
+        };
+        let mut keep_tags = vec![];
+        for tag in config.keep_tags.iter() {
+            match Regex::new(tag) {
+                Ok(re) => keep_tags.push(re),
+                Err(e) => {
+                    return Err(anyhow::Error::msg(format!(
+                        "Invalid tag regex {}: {}",
+                        tag, e
+                    )))
+                }
+            }
+        }
+        Ok(Policy {
+            registry: config.registry,
+            repository: config.repository,
+            repository_re,
+            keep_last: config.keep_last,
+            keep_tags,
+            older_than_days: config.older_than_days,
+        })
+    }
+
+    fn keeps(&self, tag: &TagRemoval, rank: usize, now: i64) -> bool {
+        if self.keep_tags.iter().any(|re| re.is_match(&tag.tag)) {
+            return true;
+        }
+        if self.keep_last.is_some_and(|n| rank < n) {
+            return true;
+        }
+        match self.older_than_days {
+            // tags without a known creation date are never considered old
+            Some(days) => match tag.created.as_deref().and_then(parse_rfc3339) {
+                Some(created) => now - created < days as i64 * 86400,
+                None => true,
+            },
+            None => false,
+        }
+    }
+}
+
+/// applies the first matching policy to every repository of the catalog, manifests read
+/// along the way are cached for `ttl` seconds
+pub async fn apply(
+    policies: &[Policy],
+    store: &dyn Store,
+    registry: &Registry,
+    ttl: usize,
+    dry_run: bool,
+) -> Result<RetentionReport, anyhow::Error> {
+    let repositories = match store.catalog_prefixed(registry.name(), "").await {
+        Ok(repositories) => repositories,
+        Err(e) => return Err(anyhow::Error::msg(format!("Failed to read catalog: {}", e))),
+    };
+    let mut report = RetentionReport {
+        dry_run,
+        ..RetentionReport::default()
+    };
+    for repository in repositories {
+        if let Some(policy) = policies.iter().find(|p| {
+            p.registry.as_ref().is_none_or(|r| r == registry.name())
+                && p.repository_re.is_match(&repository)
+        }) {
+            report
+                .repositories
+                .push(apply_repository(policy, store, registry, &repository, ttl, dry_run).await);
+        }
+    }
+    report.bytes_freed = report
+        .repositories
+        .iter()
+        .flat_map(|r| r.deleted.iter())
+        .filter_map(|t| t.size)
+        .sum();
+    Ok(report)
+}
+
+async fn apply_repository(
+    policy: &Policy,
+    store: &dyn Store,
+    registry: &Registry,
+    repository: &str,
+    ttl: usize,
+    dry_run: bool,
+) -> RepositoryRetention {
+    let mut res = RepositoryRetention {
+        name: repository.to_string(),
+        policy: policy.repository.clone(),
+        ..RepositoryRetention::default()
+    };
+    let tags = match req_tags(registry, repository).await {
+        Ok(tags) => tags.tags,
+        Err(e) => {
+            res.errors.push(e.to_string());
+            return res;
+        }
+    };
+    let mut candidates = vec![];
+    let mut uninspected = vec![];
+    for tag in tags {
+        let digest = match req_digest(registry, repository, &tag).await {
+            Ok(digest) => digest,
+            Err(e) => {
+                res.errors.push(e.to_string());
+                uninspected.push(tag.clone());
+                res.kept.push(tag);
+                continue;
+            }
+        };
+        let created = match req_image_config(store, registry, repository, &tag, ttl).await {
+            Ok(config) => config.created,
+            Err(e) => {
+                res.errors.push(format!("{}: {}", tag, e));
+                uninspected.push(tag.clone());
+                None
+            }
+        };
+        let size = match req_image_size(store, registry, repository, &tag, ttl).await {
+            Ok(size) => size.size,
+            Err(_) => None,
+        };
+        candidates.push(TagRemoval {
+            tag,
+            digest,
+            created,
+            size,
+        });
+    }
+    // most recent first, undated tags last
+    candidates.sort_by_key(|t| std::cmp::Reverse(t.created.as_deref().and_then(parse_rfc3339)));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let (kept, deleted): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .enumerate()
+        .partition(|(rank, tag)| policy.keeps(tag, *rank, now));
+    // deleting a digest removes every tag pointing to it, spare those of kept tags
+    let kept_digests: HashSet<String> = kept.iter().map(|(_, t)| t.digest.clone()).collect();
+    res.kept.extend(kept.into_iter().map(|(_, t)| t.tag));
+    for (_, tag) in deleted {
+        if kept_digests.contains(&tag.digest) {
+            res.kept.push(tag.tag);
+        } else {
+            res.deleted.push(tag);
+        }
+    }
+    if dry_run {
+        return res;
+    }
+    // an undated tag ranks last, the newest ones could go under `keep_last`
+    if !uninspected.is_empty() {
+        res.errors.push(format!(
+            "Skipped, could not inspect tags {}",
+            uninspected.join(", ")
+        ));
+        res.kept.extend(res.deleted.drain(..).map(|t| t.tag));
+        return res;
+    }
+    let mut digests: Vec<String> = res.deleted.iter().map(|t| t.digest.clone()).collect();
+    digests.sort();
+    digests.dedup();
+    for digest in digests {
+        let failure = match registry
+            .send(
+                Method::DELETE,
+                &format!("/{}/manifests/{}", repository, digest),
+                None,
+            )
+            .await
+        {
+            Ok(r) if r.status.is_success() => {
+                invalidate_manifest(store, registry, repository, &digest).await;
+                continue;
+            }
+            Ok(r) => format!("registry answered {}", r.status),
+            Err(e) => e.to_string(),
+        };
+        res.errors
+            .push(format!("Failed to delete {}: {}", digest, failure));
+        res.deleted.retain(|t| t.digest != digest);
+    }
+    if !res.deleted.is_empty() {
+        invalidate_tags(store, registry, repository).await;
+    }
+    if res.kept.is_empty() && res.errors.is_empty() {
+        if let Err(e) = uncatalog(store, registry, repository).await {
+            res.errors.push(e.to_string());
+        }
+    }
+    res
+}
+
+/// parses an RFC 3339 date to unix seconds
+fn parse_rfc3339(date: &str) -> Option<i64> {
+    let (day, time) = date.split_once(['T', 't', ' '])?;
+    let mut day = day.splitn(3, '-').map(|p| p.parse::<i64>());
+    let (year, month, day) = (day.next()?.ok()?, day.next()?.ok()?, day.next()?.ok()?);
+    let offset_at = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+    let (clock, offset) = time.split_at(offset_at);
+    let mut clock = clock
+        .split('.')
+        .next()?
+        .splitn(3, ':')
+        .map(|p| p.parse::<i64>());
+    let (hour, minute, second) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+    );
+    let offset = match offset.chars().next() {
+        Some(sign @ ('+' | '-')) => {
+            let (h, m) = offset[1..].split_once(':')?;
+            let offset = h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60;
+            if sign == '-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => 0,
+    };
+    // days since epoch of a proleptic gregorian date, from Howard Hinnant's algorithm
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(
+        keep_last: Option<usize>,
+        keep_tags: &[&str],
+        older_than_days: Option<u64>,
+    ) -> Policy {
+        Policy::compile(PolicyConfig {
+            registry: None,
+            repository: "team/*".to_string(),
+            keep_last,
+            keep_tags: keep_tags.iter().map(|t| t.to_string()).collect(),
+            older_than_days,
+        })
+        .unwrap()
+    }
+
+    fn tag(tag: &str, created: Option<&str>) -> TagRemoval {
+        TagRemoval {
+            tag: tag.to_string(),
+            created: created.map(String::from),
+            ..TagRemoval::default()
+        }
+    }
+
+    const DAY: i64 = 86400;
+    // 2021-01-31T00:00:00Z
+    const NOW: i64 = 1612051200;
+
+    #[test]
+    fn parses_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2021-01-31T00:00:00Z"), Some(NOW));
+        assert_eq!(parse_rfc3339("2021-01-31T00:00:00.123456789Z"), Some(NOW));
+        assert_eq!(parse_rfc3339("2021-01-31 02:30:00+02:30"), Some(NOW));
+        assert_eq!(parse_rfc3339("2021-01-30T22:00:00-02:00"), Some(NOW));
+        assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Some(951782400));
+        assert_eq!(parse_rfc3339("2021-01-31"), None);
+        assert_eq!(parse_rfc3339("yesterday"), None);
+    }
+
+    #[test]
+    fn matches_repository_glob() {
+        let policy = policy(Some(1), &[], None);
+        assert!(policy.repository_re.is_match("team/app"));
+        assert!(policy.repository_re.is_match("team/app/worker"));
+        assert!(!policy.repository_re.is_match("other/app"));
+        assert!(!policy.repository_re.is_match("team"));
+    }
+
+    #[test]
+    fn keeps_tags_matching_a_pattern() {
+        let policy = policy(Some(0), &["^v\\d+\\.\\d+$", "latest"], Some(1));
+        let old = Some("2020-01-01T00:00:00Z");
+        assert!(policy.keeps(&tag("v1.2", old), 5, NOW));
+        assert!(policy.keeps(&tag("latest", old), 5, NOW));
+        assert!(!policy.keeps(&tag("v1.2.3", old), 5, NOW));
+        assert!(!policy.keeps(&tag("dev", old), 5, NOW));
+    }
+
+    #[test]
+    fn keeps_the_last_tags() {
+        let policy = policy(Some(2), &[], None);
+        assert!(policy.keeps(&tag("a", None), 0, NOW));
+        assert!(policy.keeps(&tag("b", None), 1, NOW));
+        assert!(!policy.keeps(&tag("c", None), 2, NOW));
+    }
+
+    #[test]
+    fn keeps_undated_tags_under_older_than() {
+        let policy = policy(None, &[], Some(30));
+        assert!(policy.keeps(&tag("undated", None), 10, NOW));
+        assert!(policy.keeps(&tag("garbled", Some("not a date")), 10, NOW));
+    }
+
+    #[test]
+    fn keeps_last_or_younger() {
+        let policy = policy(Some(1), &[], Some(30));
+        let recent = "2021-01-21T00:00:00Z";
+        let old = "2020-12-01T00:00:00Z";
+        assert_eq!(parse_rfc3339(recent), Some(NOW - 10 * DAY));
+        // the most recent tag is kept however old
+        assert!(policy.keeps(&tag("old", Some(old)), 0, NOW));
+        // the others only while younger than the limit
+        assert!(policy.keeps(&tag("recent", Some(recent)), 1, NOW));
+        assert!(!policy.keeps(&tag("old", Some(old)), 1, NOW));
+    }
+}
@@ -0,0 +1,100 @@
+mod memory;
+mod redis;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+pub use self::{memory::MemoryStore, redis::RedisStore};
+use crate::config::{Config, StorageBackend};
+
+/// where catalogs, caches and activity feeds are kept, every call is namespaced by the
+/// name of the registry it is about
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// checks that the backend is reachable
+    async fn ping(&self) -> Result<(), anyhow::Error>;
+
+    /// replaces the whole catalog at once, readers never see it partially written
+    async fn replace_catalog(&self, ns: &str, images: &[String]) -> Result<(), anyhow::Error>;
+
+    async fn add_to_catalog(&self, ns: &str, image: &str) -> Result<(), anyhow::Error>;
+
+    async fn remove_from_catalog(&self, ns: &str, image: &str) -> Result<(), anyhow::Error>;
+
+    /// number of images in the catalog
+    async fn catalog_len(&self, ns: &str) -> Result<usize, anyhow::Error>;
+
+    /// up to `count` images from `offset` in alphabetical order, with the catalog size
+    async fn catalog_range(
+        &self,
+        ns: &str,
+        offset: usize,
+        count: usize,
+    ) -> Result<(usize, Vec<String>), anyhow::Error>;
+
+    /// number of images sorted before or equal to `image`
+    async fn catalog_rank(&self, ns: &str, image: &str) -> Result<usize, anyhow::Error>;
+
+    /// images starting with `prefix` in alphabetical order, the whole catalog when empty
+    async fn catalog_prefixed(&self, ns: &str, prefix: &str) -> Result<Vec<String>, anyhow::Error>;
+
+    /// cached tags of an image, `None` when missing or expired
+    async fn tags(&self, ns: &str, image: &str) -> Result<Option<Vec<String>>, anyhow::Error>;
+
+    async fn set_tags(
+        &self,
+        ns: &str,
+        image: &str,
+        tags: &[String],
+        ttl_secs: usize,
+    ) -> Result<(), anyhow::Error>;
+
+    /// adds a tag to a cached list, images without one are left alone
+    async fn add_tag(&self, ns: &str, image: &str, tag: &str) -> Result<(), anyhow::Error>;
+
+    async fn remove_tag(&self, ns: &str, image: &str, tag: &str) -> Result<(), anyhow::Error>;
+
+    async fn invalidate_tags(&self, ns: &str, image: &str) -> Result<(), anyhow::Error>;
+
+    /// cached manifest of an image by tag or digest, `None` when missing or expired
+    async fn manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+    ) -> Result<Option<String>, anyhow::Error>;
+
+    async fn set_manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+        manifest: &str,
+        ttl_secs: usize,
+    ) -> Result<(), anyhow::Error>;
+
+    /// drops a cached manifest, for digests deleted from the registry
+    async fn invalidate_manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+    ) -> Result<(), anyhow::Error>;
+
+    /// adds an event in front of the activity feed, keeping the `keep` most recent ones
+    async fn push_activity(&self, ns: &str, event: &str, keep: usize) -> Result<(), anyhow::Error>;
+
+    /// activity feed, most recent first
+    async fn activity(&self, ns: &str) -> Result<Vec<String>, anyhow::Error>;
+}
+
+/// opens the storage backend selected by the configuration
+pub fn open(config: &Config) -> Result<Arc<dyn Store>, anyhow::Error> {
+    match config.storage.backend {
+        StorageBackend::Redis => Ok(Arc::new(RedisStore::open(&config.redis.url)?)),
+        StorageBackend::Memory => Ok(Arc::new(MemoryStore::open(
+            config.storage.snapshot.clone(),
+        )?)),
+    }
+}
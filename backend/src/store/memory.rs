@@ -0,0 +1,461 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fs, io,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::web;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::Store;
+
+#[derive(Default)]
+struct Namespace {
+    catalog: BTreeSet<String>,
+    tags: HashMap<String, (BTreeSet<String>, Instant)>,
+    manifests: HashMap<(String, String), (String, Instant)>,
+    activity: VecDeque<String>,
+}
+
+/// content of the snapshot file, caches are not kept
+#[derive(Serialize, Deserialize, Default)]
+struct Snapshot {
+    catalogs: HashMap<String, Vec<String>>,
+    activity: HashMap<String, Vec<String>>,
+}
+
+/// store kept in the memory of the process, for deployments without redis
+///
+/// catalogs and activity feeds are written to the snapshot file after every change when
+/// one is set, on the blocking thread pool, and read back when the store is opened
+pub struct MemoryStore {
+    namespaces: Mutex<HashMap<String, Namespace>>,
+    snapshot: Option<String>,
+    // version of the state last taken for a snapshot
+    version: AtomicU64,
+    // snapshots are written one at a time, holding the version last written so that an
+    // older state never overwrites a newer one
+    written: Arc<Mutex<u64>>,
+}
+
+fn expires(ttl_secs: usize) -> Instant {
+    Instant::now() + Duration::from_secs(ttl_secs as u64)
+}
+
+impl MemoryStore {
+    /// empty store, or the content of `snapshot` when the file exists
+    pub fn open(snapshot: Option<String>) -> Result<MemoryStore, anyhow::Error> {
+        let mut namespaces: HashMap<String, Namespace> = HashMap::new();
+        if let Some(path) = &snapshot {
+            let saved = match fs::read_to_string(path) {
+                Ok(content) => match serde_json::from_str::<Snapshot>(&content) {
+                    Ok(saved) => saved,
+                    Err(e) => {
+                        return Err(anyhow::Error::msg(format!(
+                            "Failed to parse snapshot {}: {}",
+                            path, e
+                        )))
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Snapshot::default(),
+                Err(e) => {
+                    return Err(anyhow::Error::msg(format!(
+                        "Failed to read snapshot {}: {}",
+                        path, e
+                    )))
+                }
+            };
+            for (ns, catalog) in saved.catalogs {
+                namespaces.entry(ns).or_default().catalog = catalog.into_iter().collect();
+            }
+            for (ns, activity) in saved.activity {
+                namespaces.entry(ns).or_default().activity = activity.into_iter().collect();
+            }
+        }
+        Ok(MemoryStore {
+            namespaces: Mutex::new(namespaces),
+            snapshot,
+            version: AtomicU64::new(0),
+            written: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Namespace>> {
+        self.namespaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn with<R>(&self, ns: &str, f: impl FnOnce(&mut Namespace) -> R) -> R {
+        f(self.lock().entry(ns.to_string()).or_default())
+    }
+
+    /// writes the catalogs and activity feeds to a temporary file then renames it over the
+    /// snapshot, so that a crash never leaves it half written
+    async fn save(&self) -> Result<(), anyhow::Error> {
+        let path = match &self.snapshot {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let (version, content) = {
+            let namespaces = self.lock();
+            let version = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+            let snapshot = Snapshot {
+                catalogs: namespaces
+                    .iter()
+                    .map(|(ns, n)| (ns.clone(), n.catalog.iter().cloned().collect()))
+                    .collect(),
+                activity: namespaces
+                    .iter()
+                    .map(|(ns, n)| (ns.clone(), n.activity.iter().cloned().collect()))
+                    .collect(),
+            };
+            (version, serde_json::to_string(&snapshot)?)
+        };
+        let written = self.written.clone();
+        let tmp = format!("{}.tmp", path);
+        let target = path.clone();
+        let res = web::block(move || {
+            let mut written = written.lock().unwrap_or_else(PoisonError::into_inner);
+            if *written > version {
+                return Ok(());
+            }
+            fs::write(&tmp, content).and_then(|_| fs::rename(&tmp, &target))?;
+            *written = version;
+            Ok::<_, io::Error>(())
+        })
+        .await;
+        if let Err(e) = res {
+            return Err(anyhow::Error::msg(format!(
+                "Failed to write snapshot {}: {}",
+                path, e
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn replace_catalog(&self, ns: &str, images: &[String]) -> Result<(), anyhow::Error> {
+        let catalog = images.iter().cloned().collect();
+        self.with(ns, |n| n.catalog = catalog);
+        self.save().await
+    }
+
+    async fn add_to_catalog(&self, ns: &str, image: &str) -> Result<(), anyhow::Error> {
+        if self.with(ns, |n| n.catalog.insert(image.to_string())) {
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_from_catalog(&self, ns: &str, image: &str) -> Result<(), anyhow::Error> {
+        if self.with(ns, |n| n.catalog.remove(image)) {
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    async fn catalog_len(&self, ns: &str) -> Result<usize, anyhow::Error> {
+        Ok(self.with(ns, |n| n.catalog.len()))
+    }
+
+    async fn catalog_range(
+        &self,
+        ns: &str,
+        offset: usize,
+        count: usize,
+    ) -> Result<(usize, Vec<String>), anyhow::Error> {
+        Ok(self.with(ns, |n| {
+            (
+                n.catalog.len(),
+                n.catalog.iter().skip(offset).take(count).cloned().collect(),
+            )
+        }))
+    }
+
+    async fn catalog_rank(&self, ns: &str, image: &str) -> Result<usize, anyhow::Error> {
+        Ok(self.with(ns, |n| {
+            n.catalog
+                .range::<str, _>((Bound::Unbounded, Bound::Included(image)))
+                .count()
+        }))
+    }
+
+    async fn catalog_prefixed(&self, ns: &str, prefix: &str) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self.with(ns, |n| {
+            n.catalog
+                .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|i| i.starts_with(prefix))
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn tags(&self, ns: &str, image: &str) -> Result<Option<Vec<String>>, anyhow::Error> {
+        Ok(self.with(ns, |n| match n.tags.get(image) {
+            Some((tags, deadline)) if *deadline > Instant::now() && !tags.is_empty() => {
+                Some(tags.iter().cloned().collect())
+            }
+            Some(_) => {
+                n.tags.remove(image);
+                None
+            }
+            None => None,
+        }))
+    }
+
+    async fn set_tags(
+        &self,
+        ns: &str,
+        image: &str,
+        tags: &[String],
+        ttl_secs: usize,
+    ) -> Result<(), anyhow::Error> {
+        let entry = (tags.iter().cloned().collect(), expires(ttl_secs));
+        self.with(ns, |n| n.tags.insert(image.to_string(), entry));
+        Ok(())
+    }
+
+    async fn add_tag(&self, ns: &str, image: &str, tag: &str) -> Result<(), anyhow::Error> {
+        self.with(ns, |n| {
+            if let Some((tags, _)) = n.tags.get_mut(image) {
+                tags.insert(tag.to_string());
+            }
+        });
+        Ok(())
+    }
+
+    async fn remove_tag(&self, ns: &str, image: &str, tag: &str) -> Result<(), anyhow::Error> {
+        self.with(ns, |n| {
+            if let Some((tags, _)) = n.tags.get_mut(image) {
+                tags.remove(tag);
+            }
+        });
+        Ok(())
+    }
+
+    async fn invalidate_tags(&self, ns: &str, image: &str) -> Result<(), anyhow::Error> {
+        self.with(ns, |n| n.tags.remove(image));
+        Ok(())
+    }
+
+    async fn manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let key = (image.to_string(), reference.to_string());
+        Ok(self.with(ns, |n| match n.manifests.get(&key) {
+            Some((manifest, deadline)) if *deadline > Instant::now() => Some(manifest.clone()),
+            Some(_) => {
+                n.manifests.remove(&key);
+                None
+            }
+            None => None,
+        }))
+    }
+
+    async fn set_manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+        manifest: &str,
+        ttl_secs: usize,
+    ) -> Result<(), anyhow::Error> {
+        let now = Instant::now();
+        self.with(ns, |n| {
+            // expired manifests are only dropped when read, sweep those never read again
+            n.manifests.retain(|_, (_, deadline)| *deadline > now);
+            n.manifests.insert(
+                (image.to_string(), reference.to_string()),
+                (manifest.to_string(), expires(ttl_secs)),
+            );
+        });
+        Ok(())
+    }
+
+    async fn invalidate_manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+    ) -> Result<(), anyhow::Error> {
+        let key = (image.to_string(), reference.to_string());
+        self.with(ns, |n| n.manifests.remove(&key));
+        Ok(())
+    }
+
+    async fn push_activity(&self, ns: &str, event: &str, keep: usize) -> Result<(), anyhow::Error> {
+        self.with(ns, |n| {
+            n.activity.push_front(event.to_string());
+            n.activity.truncate(keep);
+        });
+        self.save().await
+    }
+
+    async fn activity(&self, ns: &str) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self.with(ns, |n| n.activity.iter().cloned().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::Path, process};
+
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    /// snapshot path unique to the test, removed beforehand
+    fn snapshot(name: &str) -> String {
+        let path = env::temp_dir().join(format!("shipyard-{}-{}.json", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[actix_rt::test]
+    async fn expires_tags() {
+        let store = MemoryStore::open(None).unwrap();
+        store
+            .set_tags("hub", "app", &strings(&["1", "2"]), 60)
+            .await
+            .unwrap();
+        store.add_tag("hub", "app", "3").await.unwrap();
+        store.remove_tag("hub", "app", "1").await.unwrap();
+        assert_eq!(
+            store.tags("hub", "app").await.unwrap(),
+            Some(strings(&["2", "3"]))
+        );
+        assert_eq!(store.tags("other", "app").await.unwrap(), None);
+
+        store
+            .set_tags("hub", "app", &strings(&["1"]), 0)
+            .await
+            .unwrap();
+        assert_eq!(store.tags("hub", "app").await.unwrap(), None);
+        // lists of untracked images are not started by a single tag
+        store.add_tag("hub", "app", "2").await.unwrap();
+        assert_eq!(store.tags("hub", "app").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn expires_manifests() {
+        let store = MemoryStore::open(None).unwrap();
+        store
+            .set_manifest("hub", "app", "1", "{}", 60)
+            .await
+            .unwrap();
+        store
+            .set_manifest("hub", "app", "2", "{}", 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.manifest("hub", "app", "1").await.unwrap(),
+            Some("{}".to_string())
+        );
+        assert_eq!(store.manifest("hub", "app", "2").await.unwrap(), None);
+
+        store
+            .set_manifest("hub", "app", "3", "{}", 0)
+            .await
+            .unwrap();
+        store
+            .set_manifest("hub", "app", "4", "{}", 60)
+            .await
+            .unwrap();
+        let namespaces = store.lock();
+        let cached: BTreeSet<&str> = namespaces["hub"]
+            .manifests
+            .keys()
+            .map(|(_, reference)| reference.as_str())
+            .collect();
+        assert_eq!(cached, ["1", "4"].iter().copied().collect());
+    }
+
+    #[actix_rt::test]
+    async fn edits_catalogs() {
+        let store = MemoryStore::open(None).unwrap();
+        store
+            .replace_catalog("hub", &strings(&["b", "a/x", "a/y"]))
+            .await
+            .unwrap();
+        store.add_to_catalog("hub", "c").await.unwrap();
+        store.remove_from_catalog("hub", "b").await.unwrap();
+        assert_eq!(store.catalog_range("hub", 0, 0).await.unwrap(), (3, vec![]));
+        assert_eq!(
+            store.catalog_range("hub", 1, 2).await.unwrap(),
+            (3, strings(&["a/y", "c"]))
+        );
+        assert_eq!(store.catalog_rank("hub", "a/y").await.unwrap(), 2);
+        assert_eq!(
+            store.catalog_prefixed("hub", "a/").await.unwrap(),
+            strings(&["a/x", "a/y"])
+        );
+        assert_eq!(
+            store.catalog_range("other", 0, 10).await.unwrap(),
+            (0, vec![])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn round_trips_snapshots() {
+        let path = snapshot("round-trip");
+        let store = MemoryStore::open(Some(path.clone())).unwrap();
+        store
+            .replace_catalog("hub", &strings(&["a", "b"]))
+            .await
+            .unwrap();
+        store
+            .set_tags("hub", "a", &strings(&["1"]), 60)
+            .await
+            .unwrap();
+        for event in &["1", "2", "3"] {
+            store.push_activity("hub", event, 2).await.unwrap();
+        }
+        store.remove_from_catalog("hub", "b").await.unwrap();
+        // the temporary file is renamed over the snapshot
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let reopened = MemoryStore::open(Some(path.clone())).unwrap();
+        assert_eq!(
+            reopened.catalog_range("hub", 0, 10).await.unwrap(),
+            (1, strings(&["a"]))
+        );
+        assert_eq!(
+            reopened.activity("hub").await.unwrap(),
+            strings(&["3", "2"])
+        );
+        // caches are not kept
+        assert_eq!(reopened.tags("hub", "a").await.unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn opens_missing_and_rejects_invalid_snapshots() {
+        let path = snapshot("invalid");
+        let store = MemoryStore::open(Some(path.clone())).unwrap();
+        assert_eq!(
+            store.catalog_range("hub", 0, 10).await.unwrap(),
+            (0, vec![])
+        );
+        fs::write(&path, "{").unwrap();
+        assert!(MemoryStore::open(Some(path.clone())).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
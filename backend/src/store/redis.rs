@@ -0,0 +1,307 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex, PoisonError,
+};
+
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use super::Store;
+use crate::metrics;
+
+/// images written per command when the catalog is replaced
+const CATALOG_CHUNK: usize = 1000;
+
+/// seconds before the keys of an interrupted catalog replacement are dropped
+const CATALOG_TMP_TTL: usize = 600;
+
+/// store kept in redis, shared by every worker and surviving restarts
+///
+/// the catalog is a sorted set with every score at 0, so that it can be read by
+/// lexicographic ranges
+pub struct RedisStore {
+    client: redis::Client,
+    // connected on first use so that the api starts while redis is down, the manager
+    // then reconnects on its own and is cheap to clone, every call gets its own handle
+    con: Mutex<Option<ConnectionManager>>,
+}
+
+fn key(ns: &str, key: &str) -> String {
+    format!("{}:{}", ns, key)
+}
+
+fn tags_key(ns: &str, image: &str) -> String {
+    key(ns, &format!("tags:{}", image))
+}
+
+fn manifest_key(ns: &str, image: &str, reference: &str) -> String {
+    key(ns, &format!("manifest:{}:{}", image, reference))
+}
+
+/// counts failed commands for the metrics
+fn counted<T>(res: redis::RedisResult<T>) -> Result<T, anyhow::Error> {
+    res.map_err(|e| {
+        metrics::redis_error();
+        e.into()
+    })
+}
+
+impl RedisStore {
+    /// checks the url, the connection is only made on first use
+    pub fn open(url: &str) -> Result<RedisStore, anyhow::Error> {
+        match redis::Client::open(url) {
+            Ok(client) => Ok(RedisStore {
+                client,
+                con: Mutex::new(None),
+            }),
+            Err(e) => Err(anyhow::Error::msg(format!("Invalid redis url: {}", e))),
+        }
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager, anyhow::Error> {
+        if let Some(con) = &*self.con.lock().unwrap_or_else(PoisonError::into_inner) {
+            return Ok(con.clone());
+        }
+        let con = match ConnectionManager::new(self.client.clone()).await {
+            Ok(con) => con,
+            Err(e) => {
+                metrics::redis_error();
+                return Err(anyhow::Error::msg(format!(
+                    "Failed connecting redis: {}",
+                    e
+                )));
+            }
+        };
+        *self.con.lock().unwrap_or_else(PoisonError::into_inner) = Some(con.clone());
+        Ok(con)
+    }
+}
+
+#[async_trait]
+impl Store for RedisStore {
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        counted(
+            redis::cmd("PING")
+                .query_async(&mut self.connection().await?)
+                .await,
+        )
+    }
+
+    async fn replace_catalog(&self, ns: &str, images: &[String]) -> Result<(), anyhow::Error> {
+        static REPLACEMENTS: AtomicUsize = AtomicUsize::new(0);
+        let mut con = self.connection().await?;
+        let catalog = key(ns, "catalog");
+        if images.is_empty() {
+            counted(con.del::<_, ()>(&catalog).await)?;
+            return Ok(());
+        }
+        // refreshes of a registry being reloaded may overlap, never share temporary keys
+        let tmp = format!(
+            "{}:tmp:{}",
+            catalog,
+            REPLACEMENTS.fetch_add(1, Ordering::Relaxed)
+        );
+        for chunk in images.chunks(CATALOG_CHUNK) {
+            let scored: Vec<(usize, &String)> = chunk.iter().map(|i| (0, i)).collect();
+            counted(
+                redis::pipe()
+                    .zadd_multiple(&tmp, &scored)
+                    .expire(&tmp, CATALOG_TMP_TTL)
+                    .query_async::<_, ()>(&mut con)
+                    .await,
+            )?;
+        }
+        // RENAME carries the expiry over, drop it on the new catalog
+        counted(
+            redis::pipe()
+                .atomic()
+                .rename(&tmp, &catalog)
+                .persist(&catalog)
+                .query_async(&mut con)
+                .await,
+        )
+    }
+
+    async fn add_to_catalog(&self, ns: &str, image: &str) -> Result<(), anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .zadd::<_, _, _, ()>(key(ns, "catalog"), image, 0)
+                .await,
+        )
+    }
+
+    async fn remove_from_catalog(&self, ns: &str, image: &str) -> Result<(), anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .zrem::<_, _, ()>(key(ns, "catalog"), image)
+                .await,
+        )
+    }
+
+    async fn catalog_len(&self, ns: &str) -> Result<usize, anyhow::Error> {
+        counted(self.connection().await?.zcard(key(ns, "catalog")).await)
+    }
+
+    async fn catalog_range(
+        &self,
+        ns: &str,
+        offset: usize,
+        count: usize,
+    ) -> Result<(usize, Vec<String>), anyhow::Error> {
+        let catalog = key(ns, "catalog");
+        counted(
+            redis::pipe()
+                .atomic()
+                .zcard(&catalog)
+                .zrange(&catalog, offset as isize, (offset + count) as isize - 1)
+                .query_async(&mut self.connection().await?)
+                .await,
+        )
+    }
+
+    async fn catalog_rank(&self, ns: &str, image: &str) -> Result<usize, anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .zlexcount(key(ns, "catalog"), "-".to_string(), format!("[{}", image))
+                .await,
+        )
+    }
+
+    async fn catalog_prefixed(&self, ns: &str, prefix: &str) -> Result<Vec<String>, anyhow::Error> {
+        let mut con = self.connection().await?;
+        if prefix.is_empty() {
+            return counted(con.zrange(key(ns, "catalog"), 0, -1).await);
+        }
+        let mut max = format!("[{}", prefix).into_bytes();
+        max.push(0xff);
+        counted(
+            con.zrangebylex(key(ns, "catalog"), format!("[{}", prefix).into_bytes(), max)
+                .await,
+        )
+    }
+
+    async fn tags(&self, ns: &str, image: &str) -> Result<Option<Vec<String>>, anyhow::Error> {
+        let mut tags: Vec<String> =
+            counted(self.connection().await?.smembers(tags_key(ns, image)).await)?;
+        // redis drops empty sets, an empty list is never cached
+        if tags.is_empty() {
+            return Ok(None);
+        }
+        tags.sort();
+        Ok(Some(tags))
+    }
+
+    async fn set_tags(
+        &self,
+        ns: &str,
+        image: &str,
+        tags: &[String],
+        ttl_secs: usize,
+    ) -> Result<(), anyhow::Error> {
+        if tags.is_empty() {
+            return Ok(());
+        }
+        counted(
+            redis::pipe()
+                .atomic()
+                .del(tags_key(ns, image))
+                .sadd(tags_key(ns, image), tags)
+                .expire(tags_key(ns, image), ttl_secs)
+                .query_async(&mut self.connection().await?)
+                .await,
+        )
+    }
+
+    async fn add_tag(&self, ns: &str, image: &str, tag: &str) -> Result<(), anyhow::Error> {
+        let mut con = self.connection().await?;
+        if counted(con.exists::<_, bool>(tags_key(ns, image)).await)? {
+            counted(con.sadd::<_, _, ()>(tags_key(ns, image), tag).await)?;
+        }
+        Ok(())
+    }
+
+    async fn remove_tag(&self, ns: &str, image: &str, tag: &str) -> Result<(), anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .srem::<_, _, ()>(tags_key(ns, image), tag)
+                .await,
+        )
+    }
+
+    async fn invalidate_tags(&self, ns: &str, image: &str) -> Result<(), anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .del::<_, ()>(tags_key(ns, image))
+                .await,
+        )
+    }
+
+    async fn manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+    ) -> Result<Option<String>, anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .get(manifest_key(ns, image, reference))
+                .await,
+        )
+    }
+
+    async fn set_manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+        manifest: &str,
+        ttl_secs: usize,
+    ) -> Result<(), anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .set_ex::<_, _, ()>(manifest_key(ns, image, reference), manifest, ttl_secs)
+                .await,
+        )
+    }
+
+    async fn invalidate_manifest(
+        &self,
+        ns: &str,
+        image: &str,
+        reference: &str,
+    ) -> Result<(), anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .del::<_, ()>(manifest_key(ns, image, reference))
+                .await,
+        )
+    }
+
+    async fn push_activity(&self, ns: &str, event: &str, keep: usize) -> Result<(), anyhow::Error> {
+        counted(
+            redis::pipe()
+                .atomic()
+                .lpush(key(ns, "activity"), event)
+                .ltrim(key(ns, "activity"), 0, keep as isize - 1)
+                .query_async(&mut self.connection().await?)
+                .await,
+        )
+    }
+
+    async fn activity(&self, ns: &str) -> Result<Vec<String>, anyhow::Error> {
+        counted(
+            self.connection()
+                .await?
+                .lrange(key(ns, "activity"), 0, -1)
+                .await,
+        )
+    }
+}
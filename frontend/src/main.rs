@@ -1,10 +1,27 @@
-use anyhow::{self, Error};
+mod route;
+
+use gloo_events::EventListener;
 use material_yew::select::ListIndex::Single;
 use material_yew::{select::ListIndex, MatList, MatListItem};
-use shipyard::{get_manifest, DockerManifest, Repos, Tags};
-use yew::services::ConsoleService;
+use route::Route;
+use shipyard::api::{
+    CompareImages, DeleteManifest, Endpoint, GetClientConfig, GetConfig, GetManifest,
+    GetRetentionReport, GetSize, ListImagesPage, ListRegistries, ListTags, SearchImages,
+};
+use shipyard::{
+    format_size, CatalogPage, ClientConfig, DeleteReport, DockerManifest, ImageConfig, ImageDiff,
+    ImageSize, ManifestConfig, RetentionReport, Tags,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use yew::services::{
+    timeout::{TimeoutService, TimeoutTask},
+    ConsoleService, DialogService,
+};
 use yew::{
-    format::{Json, Nothing},
+    format::Nothing,
     prelude::*,
     services::{
         fetch::Response,
@@ -13,14 +30,40 @@ use yew::{
     },
 };
 
+/// sizes requested at once, each costs the backend a manifest per platform
+const SIZE_REQUESTS: usize = 4;
+
 enum Msg {
     Error,
+    Failure(String),
+    DismissFailure,
+    GetClientConfig,
+    ReceiveClientConfig(Result<ClientConfig, anyhow::Error>),
+    GetRegistries,
+    ReceiveResponseRegistries(Result<Vec<String>, anyhow::Error>),
+    SelectRegistry(String),
+    Navigate(Route),
+    RouteChanged,
     GetList,
+    SearchInput(String),
+    Search,
+    GetPage(usize),
     GetImage(String),
     GetManifest(String, String),
     ReceiveResponseTags(Result<Tags, anyhow::Error>),
-    ReceiveResponse(Result<Repos, anyhow::Error>),
-    ReceiveResponseManifest(Result<DockerManifest, anyhow::Error>),
+    ReceiveResponse(Result<CatalogPage, anyhow::Error>),
+    ReceiveResponseManifest(Result<DockerManifest, anyhow::Error>, Option<String>),
+    ReceiveResponseConfig(Box<Result<ImageConfig, anyhow::Error>>),
+    ReceiveResponseSize(String, Result<ImageSize, anyhow::Error>),
+    CompareInput(String),
+    Compare,
+    GetDiff(String, String, String, String),
+    ReceiveResponseDiff(Box<Result<ImageDiff, anyhow::Error>>),
+    DeleteTag(bool),
+    ConfirmDelete(DeleteReport),
+    ReceiveResponseDelete(Result<DeleteReport, anyhow::Error>),
+    GetRetention,
+    ReceiveResponseRetention(Result<RetentionReport, anyhow::Error>),
 }
 
 fn render(item: &String) -> Html {
@@ -29,7 +72,9 @@ fn render(item: &String) -> Html {
 
 fn image_list_callback(val: ListIndex, list: Vec<String>) -> Msg {
     if let Single(index) = val {
-        Msg::GetImage(list[index.expect("Error ListIndex type")].clone())
+        Msg::Navigate(Route::Repo(
+            list[index.expect("Error ListIndex type")].clone(),
+        ))
     } else {
         Msg::Error
     }
@@ -37,7 +82,10 @@ fn image_list_callback(val: ListIndex, list: Vec<String>) -> Msg {
 
 fn image_tags_callback(name: String, val: ListIndex, list: Vec<String>) -> Msg {
     if let Single(index) = val {
-        Msg::GetManifest(name, list[index.expect("Error ListIndex type")].clone())
+        Msg::Navigate(Route::Tag(
+            name,
+            list[index.expect("Error ListIndex type")].clone(),
+        ))
     } else {
         Msg::Error
     }
@@ -45,21 +93,161 @@ fn image_tags_callback(name: String, val: ListIndex, list: Vec<String>) -> Msg {
 
 struct Model {
     task: Option<FetchTask>,
-    list: Option<Repos>,
+    list: Option<CatalogPage>,
     link: ComponentLink<Self>,
     tags: Option<Tags>,
     manifest: Option<DockerManifest>,
-    error: Option<Error>,
+    /// digest the registry answered for the selected tag
+    digest: Option<String>,
+    error: Option<anyhow::Error>,
+    config_task: Option<FetchTask>,
+    /// config of the selected tag, or why it could not be read
+    config: Option<Result<ImageConfig, String>>,
+    /// size requests in flight by tag
+    size_tasks: HashMap<String, FetchTask>,
+    /// tags waiting for a size request, as image and tag
+    size_queue: VecDeque<(String, String)>,
+    sizes: HashMap<String, ImageSize>,
+    selected: Option<(String, String)>,
+    retention: Option<RetentionReport>,
+    search: String,
+    search_timeout: Option<TimeoutTask>,
+    registries_task: Option<FetchTask>,
+    registries: Vec<String>,
+    registry: String,
+    failure: Option<String>,
+    api_base: String,
+    /// events token of the api, typed before the first deletion
+    delete_token: Option<String>,
+    tags_task: Option<FetchTask>,
+    manifest_task: Option<FetchTask>,
+    /// search the list was requested with, `search` is what is typed
+    searched: String,
+    /// reference typed to compare the selected tag with
+    compare: String,
+    /// base and target images of the comparison shown, as name and reference
+    compared: Option<(String, String, String, String)>,
+    diff: Option<ImageDiff>,
+    diff_task: Option<FetchTask>,
+    _hashchange: EventListener,
 }
 
 impl Model {
+    fn request<E: Endpoint>(&self, endpoint: &E) -> Request<Nothing> {
+        Request::builder()
+            .method(E::METHOD.as_str())
+            .uri(format!(
+                "{}{}",
+                self.api_base.trim_end_matches('/'),
+                endpoint.url()
+            ))
+            .body(Nothing)
+            .expect("Could not build request")
+    }
+
+    /// sends `endpoint`, `msg` turns its parsed answer into a message
+    fn fetch<E: Endpoint + 'static>(
+        &self,
+        endpoint: &E,
+        msg: impl Fn(Result<E::Response, anyhow::Error>) -> Msg + 'static,
+    ) -> FetchTask {
+        self.send::<E>(self.request(endpoint), msg)
+    }
+
+    fn send<E: Endpoint + 'static>(
+        &self,
+        request: Request<Nothing>,
+        msg: impl Fn(Result<E::Response, anyhow::Error>) -> Msg + 'static,
+    ) -> FetchTask {
+        let callback =
+            self.link
+                .callback(move |response: Response<Result<String, anyhow::Error>>| {
+                    let status = response.status().as_u16();
+                    msg(response
+                        .into_body()
+                        .and_then(|body| Ok(E::parse(status, &body)?)))
+                });
+        FetchService::fetch(request, callback).expect("failed to start request")
+    }
+
+    /// starts size requests from the queue, up to `SIZE_REQUESTS` at once
+    fn fetch_sizes(&mut self) {
+        while self.size_tasks.len() < SIZE_REQUESTS {
+            let (image, tag) = match self.size_queue.pop_front() {
+                Some(next) => next,
+                None => return,
+            };
+            let endpoint = GetSize {
+                registry: self.registry.clone(),
+                image,
+                reference: tag.clone(),
+            };
+            let name = tag.clone();
+            let task = self.fetch(&endpoint, move |response| {
+                Msg::ReceiveResponseSize(name.clone(), response)
+            });
+            self.size_tasks.insert(tag, task);
+        }
+    }
+
+    /// shows `route` by writing it to the fragment, `replace` to not add a history entry
+    fn navigate(&mut self, route: Route, replace: bool) -> ShouldRender {
+        let hash = route.to_hash(&self.registry);
+        let location = yew::utils::window().location();
+        match location.hash() {
+            // no hashchange event for the same fragment
+            Ok(current) if current == hash => self.update(Msg::RouteChanged),
+            _ if replace => {
+                if let Err(e) = location.replace(&hash) {
+                    ConsoleService::error(&format!("failed to replace location: {:?}", e));
+                }
+                false
+            }
+            _ => {
+                if let Err(e) = location.set_hash(&hash) {
+                    ConsoleService::error(&format!("failed to set location: {:?}", e));
+                }
+                false
+            }
+        }
+    }
+
+    fn view_failure(&self) -> Html {
+        match &self.failure {
+            Some(failure) => html! {
+                <p class="failure">
+                    { failure }
+                    <button onclick=self.link.callback(|_| Msg::DismissFailure)>{ "x" }</button>
+                </p>
+            },
+            None => html! {},
+        }
+    }
+
+    fn view_registries(&self) -> Html {
+        html! {
+            <select onchange=self.link.callback(|e: ChangeData| match e {
+                ChangeData::Select(select) => Msg::SelectRegistry(select.value()),
+                _ => Msg::Error,
+            })>
+                { self.registries.iter().map(|name| {
+                    let selected = *name == self.registry;
+                    html! {<option value=name.clone() selected=selected>{ name }</option>}
+                }).collect::<Html>() }
+            </select>
+        }
+    }
+
     fn view_tags(&self) -> Html {
         html! {
                 match self.tags.clone() {
                 Some(ref tags) => {
                     let tags_cp = tags.clone();
                     html! {<MatList onaction= self.link.callback(move |val| image_tags_callback(tags_cp.name.clone(), val, tags_cp.tags.to_vec()))>
-                        { tags.tags.iter().map(|i| render(i)).collect::<Html>() }
+                        { tags.tags.iter().map(|i| match self.sizes.get(i).and_then(|s| s.size) {
+                            Some(size) => render(&format!("{} ({})", i, format_size(size))),
+                            None => render(i),
+                        }).collect::<Html>() }
                     </MatList>}
                 }
                 None => {
@@ -70,6 +258,33 @@ impl Model {
         }
     }
 
+    fn view_search(&self) -> Html {
+        html! {
+            <input type="search" placeholder="Search images" value=self.search.clone()
+                oninput=self.link.callback(|e: InputData| Msg::SearchInput(e.value)) />
+        }
+    }
+
+    fn view_pages(&self) -> Html {
+        let list = match &self.list {
+            Some(list) => list,
+            None => return html! {},
+        };
+        let (page, pages) = (list.page, list.total.div_ceil(list.page_size.max(1)).max(1));
+        let (first, last) = (page <= 1, list.next.is_none());
+        html! {
+            <div>
+                <button disabled=first onclick=self.link.callback(move |_| Msg::GetPage(page - 1))>
+                    { "<" }
+                </button>
+                { format!(" page {} of {} ({} images) ", page, pages, list.total) }
+                <button disabled=last onclick=self.link.callback(move |_| Msg::GetPage(page + 1))>
+                    { ">" }
+                </button>
+            </div>
+        }
+    }
+
     fn view_image_list(&self) -> Html {
         match self.list.clone() {
             Some(list) => {
@@ -89,21 +304,341 @@ impl Model {
     }
 
     fn view_infos(&self) -> Html {
-        match &self.error {
-            Some(e) => html! {e},
-            None => match self.manifest.clone() {
-                Some(man) => match man {
-                    DockerManifest::V1(man) => html! {<MatList>
-                        { render(&format!("V1 {}", &man.architecture)) }
-                    </MatList> },
-                    DockerManifest::V2(_) => html! {},
-                    DockerManifest::V2List(man) => html! {<MatList>
-                        { man.manifests.unwrap().iter().map(|i| render(&format!("V2 {}/{}",i.platform.clone().unwrap().os , i.platform.clone().unwrap().architecture))).collect::<Html>() }
-                    </MatList> },
-                },
-                None => html! {<p>{"Select image and tag"}</p>},
+        let man = match (&self.error, &self.manifest) {
+            (Some(e), _) => return html! {<p>{ e.to_string() }</p>},
+            (None, Some(man)) => man,
+            (None, None) => return html! {<p>{"Select image and tag"}</p>},
+        };
+        let (img, tag) = self.selected.clone().unwrap_or_default();
+        let mut rows = vec![
+            ("digest", self.digest.clone().unwrap_or_default()),
+            ("media type", man.media_type().to_string()),
+        ];
+        if let DockerManifest::V1(man) = man {
+            rows.push(("architecture", man.architecture.clone()));
+        }
+        if let DockerManifest::OciManifest(man) = man {
+            if let Some(artifact_type) = &man.artifact_type {
+                rows.push(("artifact type", artifact_type.clone()));
+            }
+        }
+        if let Some(config) = man.config() {
+            rows.push(("config", config.digest.clone()));
+        }
+        // a list has no size of its own, the backend sums its platforms
+        let size = man
+            .size()
+            .or_else(|| self.sizes.get(&tag).and_then(|s| s.size));
+        if let Some(size) = size {
+            rows.push(("total size", format_size(size)));
+        }
+        if let Some(created) = self.image_config().and_then(|c| c.created.clone()) {
+            rows.push(("created", created));
+        }
+        html! {
+            <>
+            <h3>{ format!("{}:{}", img, tag) }</h3>
+            <table class="detail">
+                { rows.into_iter().map(|(name, value)| html! {
+                    <tr><th>{ name }</th><td>{ value }</td></tr>
+                }).collect::<Html>() }
+            </table>
+            { self.view_layers(man) }
+            { self.view_platforms(&img, man) }
+            </>
+        }
+    }
+
+    fn view_layers(&self, man: &DockerManifest) -> Html {
+        if man.layers().is_empty() {
+            return html! {};
+        }
+        html! {
+            <table class="detail">
+                <tr><th>{ "#" }</th><th>{ "layer" }</th><th>{ "media type" }</th><th>{ "size" }</th></tr>
+                { man.layers().iter().enumerate().map(|(i, layer)| html! {
+                    <tr>
+                        <td>{ i + 1 }</td>
+                        <td>{ &layer.digest }</td>
+                        <td>{ &layer.media_type }</td>
+                        <td>{ format_size(layer.size) }</td>
+                    </tr>
+                }).collect::<Html>() }
+            </table>
+        }
+    }
+
+    fn view_platforms(&self, img: &str, man: &DockerManifest) -> Html {
+        let platforms = match man.platforms() {
+            Some(platforms) => platforms,
+            None => return html! {},
+        };
+        html! {
+            <table class="detail">
+                <tr>
+                    <th>{ "os" }</th><th>{ "arch" }</th><th>{ "variant" }</th>
+                    <th>{ "features" }</th><th>{ "size" }</th><th>{ "digest" }</th>
+                </tr>
+                { platforms.iter().map(|p| {
+                    let route = Route::Tag(img.to_string(), p.digest.clone());
+                    let onclick = self.link.callback(move |_| Msg::Navigate(route.clone()));
+                    let (os, arch, variant, features) = match &p.platform {
+                        Some(platform) => (
+                            platform.os.clone(),
+                            platform.architecture.clone(),
+                            platform.variant.clone().unwrap_or_default(),
+                            platform.features.clone().unwrap_or_default().join(", "),
+                        ),
+                        // attestations and other artifacts of an OCI index
+                        None => (
+                            p.artifact_type.clone().unwrap_or_else(|| "unknown platform".to_string()),
+                            String::new(),
+                            String::new(),
+                            String::new(),
+                        ),
+                    };
+                    html! {
+                        <tr class="link" onclick=onclick>
+                            <td>{ os }</td>
+                            <td>{ arch }</td>
+                            <td>{ variant }</td>
+                            <td>{ features }</td>
+                            <td>{ self.view_platform_size(&p.digest) }</td>
+                            <td>{ &p.digest }</td>
+                        </tr>
+                    }
+                }).collect::<Html>() }
+            </table>
+        }
+    }
+
+    fn view_retention(&self) -> Html {
+        let report = match &self.retention {
+            Some(report) => report,
+            None => return html! {},
+        };
+        let mut lines = vec![format!(
+            "{} {}",
+            if report.dry_run {
+                "would free"
+            } else {
+                "freed"
+            },
+            format_size(report.bytes_freed)
+        )];
+        for repository in report.repositories.iter() {
+            lines.push(format!(
+                "{} ({}): keep {}, delete {}",
+                repository.name,
+                repository.policy,
+                repository.kept.len(),
+                repository.deleted.len()
+            ));
+            for tag in repository.deleted.iter() {
+                lines.push(format!(
+                    "{}:{} {} {}",
+                    repository.name,
+                    tag.tag,
+                    tag.created.as_deref().unwrap_or("unknown date"),
+                    tag.size.map(format_size).unwrap_or_default()
+                ));
+            }
+            for error in repository.errors.iter() {
+                lines.push(format!("{}: {}", repository.name, error));
+            }
+        }
+        html! {<MatList>
+            { lines.iter().map(render).collect::<Html>() }
+        </MatList>}
+    }
+
+    fn view_delete(&self) -> Html {
+        match &self.selected {
+            Some(_) => html! {
+                <button onclick=self.link.callback(|_| Msg::DeleteTag(false))>
+                    { "Delete tag" }
+                </button>
             },
+            None => html! {},
+        }
+    }
+
+    fn view_dockerfile(&self) -> Html {
+        // lists have no history of their own, their platforms do
+        let man = match &self.manifest {
+            Some(man) if man.platforms().is_none() => man,
+            _ => return html! {},
+        };
+        let steps = match man.build_steps(self.image_config()) {
+            Ok(steps) if steps.is_empty() => return html! {},
+            Ok(steps) => steps,
+            Err(e) => return html! {<p>{ e.to_string() }</p>},
+        };
+        html! {
+            <>
+            <h4>{ "Dockerfile" }</h4>
+            <table class="detail dockerfile">
+                { steps.iter().map(|step| html! {
+                    <tr title=step.created.clone().unwrap_or_default()>
+                        <td><code>{ step.instruction() }</code></td>
+                        <td>{ step.size.map(format_size).unwrap_or_default() }</td>
+                    </tr>
+                }).collect::<Html>() }
+            </table>
+            </>
+        }
+    }
+
+    fn view_compare(&self) -> Html {
+        if self.selected.is_none() {
+            return html! {};
+        }
+        html! {
+            <div>
+                <input type="text" placeholder="tag, digest or image:tag" value=self.compare.clone()
+                    oninput=self.link.callback(|e: InputData| Msg::CompareInput(e.value)) />
+                <button onclick=self.link.callback(|_| Msg::Compare)>{ "Compare" }</button>
+            </div>
+        }
+    }
+
+    fn view_diff(&self) -> Html {
+        let diff = match &self.diff {
+            Some(diff) => diff,
+            None => return html! {<p>{ "Comparing..." }</p>},
+        };
+        let (base, target) = (&diff.base, &diff.target);
+        let size = |size: Option<usize>| size.map(format_size).unwrap_or_default();
+        let delta = match diff.size_delta {
+            Some(delta) if delta < 0 => format!("-{}", format_size(delta.unsigned_abs() as usize)),
+            Some(delta) => format!("+{}", format_size(delta as usize)),
+            None => String::new(),
+        };
+        let layer = |layer: Option<&ManifestConfig>| match layer {
+            Some(layer) => format!("{} ({})", layer.digest, format_size(layer.size)),
+            None => String::new(),
+        };
+        // shared layers on both sides, then the ones each image has alone
+        let layers = diff
+            .shared_layers
+            .iter()
+            .map(|l| (Some(l), Some(l)))
+            .chain(diff.base_layers.iter().map(|l| (Some(l), None)))
+            .chain(diff.target_layers.iter().map(|l| (None, Some(l))));
+        let platforms = diff
+            .base_platforms
+            .iter()
+            .map(|p| (p.clone(), String::new()))
+            .chain(
+                diff.target_platforms
+                    .iter()
+                    .map(|p| (String::new(), p.clone())),
+            );
+        let row = |name: String, base: String, target: String| {
+            let class = if base != target { "changed" } else { "" };
+            html! {<tr class=class><th>{ name }</th><td>{ base }</td><td>{ target }</td></tr>}
+        };
+        html! {
+            <>
+            <table class="detail diff">
+                <tr>
+                    <th></th>
+                    <th>{ format!("{}:{}", base.image, base.reference) }</th>
+                    <th>{ format!("{}:{}", target.image, target.reference) }</th>
+                </tr>
+                { row("digest".to_string(), base.digest.clone(), target.digest.clone()) }
+                { row(
+                    "platform".to_string(),
+                    base.platform.clone().unwrap_or_default(),
+                    target.platform.clone().unwrap_or_default(),
+                ) }
+                { row("created".to_string(), base.created.clone().unwrap_or_default(), target.created.clone().unwrap_or_default()) }
+                { row("size".to_string(), size(base.size), format!("{} ({})", size(target.size), delta)) }
+            </table>
+            <h4>{ format!("Layers: {} shared", diff.shared_layers.len()) }</h4>
+            <table class="detail diff">
+                { layers.map(|(b, t)| row(String::new(), layer(b), layer(t))).collect::<Html>() }
+            </table>
+            <h4>{ "Config" }</h4>
+            <table class="detail diff">
+                { diff.config.iter().map(|change| row(
+                    match &change.key {
+                        Some(key) => format!("{} {}", change.field, key),
+                        None => change.field.clone(),
+                    },
+                    change.base.clone().unwrap_or_default(),
+                    change.target.clone().unwrap_or_default(),
+                )).collect::<Html>() }
+            </table>
+            <h4>{ "History" }</h4>
+            <table class="detail diff">
+                { diff.history.iter().map(|step| row(
+                    String::new(),
+                    step.base.clone().unwrap_or_default(),
+                    step.target.clone().unwrap_or_default(),
+                )).collect::<Html>() }
+            </table>
+            <h4>{ "Platforms" }</h4>
+            <table class="detail diff">
+                { platforms.map(|(b, t)| row(String::new(), b, t)).collect::<Html>() }
+            </table>
+            </>
+        }
+    }
+
+    fn view_platform_size(&self, digest: &str) -> String {
+        self.sizes
+            .values()
+            .flat_map(|s| s.platforms.iter())
+            .find(|p| p.digest == digest)
+            .and_then(|p| p.size)
+            .map(format_size)
+            .unwrap_or_default()
+    }
+
+    fn image_config(&self) -> Option<&ImageConfig> {
+        self.config.as_ref()?.as_ref().ok()
+    }
+
+    fn view_config(&self) -> Html {
+        let config = match &self.config {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => return html! {<p>{ format!("Failed to get image config: {}", e) }</p>},
+            None => return html! {},
+        };
+        let mut lines = vec![format!("platform: {}/{}", config.os, config.architecture)];
+        if let Some(c) = &config.config {
+            if let Some(user) = &c.user {
+                lines.push(format!("user: {}", user));
+            }
+            if let Some(working_dir) = &c.working_dir {
+                lines.push(format!("workdir: {}", working_dir));
+            }
+            if let Some(entrypoint) = &c.entrypoint {
+                lines.push(format!("entrypoint: {}", entrypoint.join(" ")));
+            }
+            if let Some(cmd) = &c.cmd {
+                lines.push(format!("cmd: {}", cmd.join(" ")));
+            }
+            if let Some(ports) = &c.exposed_ports {
+                let mut ports = ports.keys().cloned().collect::<Vec<String>>();
+                ports.sort();
+                lines.push(format!("ports: {}", ports.join(", ")));
+            }
+            for env in c.env.iter().flatten() {
+                lines.push(format!("env: {}", env));
+            }
+            if let Some(labels) = &c.labels {
+                let mut labels = labels.iter().collect::<Vec<_>>();
+                labels.sort();
+                for (key, value) in labels {
+                    lines.push(format!("label: {}={}", key, value));
+                }
+            }
         }
+        html! {<MatList>
+            { lines.iter().map(render).collect::<Html>() }
+        </MatList>}
     }
 }
 
@@ -112,98 +647,380 @@ impl Component for Model {
     type Properties = ();
 
     fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        link.send_message(Msg::GetClientConfig);
+        let hashchange = {
+            let link = link.clone();
+            EventListener::new(&yew::utils::window(), "hashchange", move |_| {
+                link.send_message(Msg::RouteChanged)
+            })
+        };
         Model {
             task: None,
             tags: None,
             link,
             list: None,
             manifest: None,
+            digest: None,
             error: None,
+            config_task: None,
+            config: None,
+            size_tasks: HashMap::new(),
+            size_queue: VecDeque::new(),
+            sizes: HashMap::new(),
+            selected: None,
+            retention: None,
+            search: String::new(),
+            search_timeout: None,
+            registries_task: None,
+            registries: vec![],
+            registry: String::new(),
+            failure: None,
+            api_base: String::new(),
+            delete_token: None,
+            tags_task: None,
+            manifest_task: None,
+            searched: String::new(),
+            compare: String::new(),
+            compared: None,
+            diff: None,
+            diff_task: None,
+            _hashchange: hashchange,
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
+            Msg::GetClientConfig => {
+                // next to index.html, wherever the frontend is served from
+                let request = Request::get("config.json")
+                    .body(Nothing)
+                    .expect("Could not build request");
+                self.registries_task =
+                    Some(self.send::<GetClientConfig>(request, Msg::ReceiveClientConfig));
+                false
+            }
+            Msg::ReceiveClientConfig(response) => {
+                match response {
+                    Ok(config) => self.api_base = config.api_base,
+                    Err(e) => ConsoleService::error(&format!(
+                        "failed to get config.json, the api is expected on the same origin: {}",
+                        e
+                    )),
+                }
+                self.update(Msg::GetRegistries)
+            }
+            Msg::GetRegistries => {
+                self.registries_task =
+                    Some(self.fetch(&ListRegistries, Msg::ReceiveResponseRegistries));
+                false
+            }
+            Msg::ReceiveResponseRegistries(response) => match response {
+                Ok(registries) => {
+                    if !registries.contains(&self.registry) {
+                        self.registry = registries.first().cloned().unwrap_or_default();
+                    }
+                    self.registries = registries;
+                    self.update(Msg::RouteChanged);
+                    true
+                }
+                Err(e) => self.update(Msg::Failure(format!("Failed to get registries: {}", e))),
+            },
+            Msg::SelectRegistry(registry) => {
+                self.registry = registry;
+                self.retention = None;
+                self.size_tasks.clear();
+                self.size_queue.clear();
+                self.sizes.clear();
+                self.update(Msg::GetList)
+            }
+            Msg::Navigate(route) => self.navigate(route, false),
+            Msg::RouteChanged => {
+                // applied once the registries are known
+                if self.registries.is_empty() {
+                    return false;
+                }
+                let hash = yew::utils::window().location().hash().unwrap_or_default();
+                let (route, registry) = Route::parse(&hash);
+                if let Some(registry) = registry {
+                    if registry != self.registry && self.registries.contains(&registry) {
+                        self.registry = registry;
+                        self.retention = None;
+                        self.list = None;
+                        self.tags = None;
+                    }
+                }
+                let search = match &route {
+                    Route::Search(q) => q.clone(),
+                    _ => String::new(),
+                };
+                // home keeps asking which images to show until the list is requested
+                if search != self.searched || (self.list.is_none() && route != Route::Home) {
+                    self.search = search.clone();
+                    self.searched = search;
+                    self.update(Msg::GetPage(1));
+                }
+                let shown = self.tags.as_ref().map(|t| t.name.clone());
+                if !matches!(route, Route::Compare(..)) {
+                    self.compared = None;
+                    self.diff = None;
+                    self.diff_task = None;
+                }
+                match route {
+                    Route::Home | Route::Search(_) => {
+                        self.tags = None;
+                        self.selected = None;
+                        self.manifest = None;
+                        self.config = None;
+                    }
+                    Route::Repo(name) if shown.as_ref() != Some(&name) => {
+                        self.update(Msg::GetImage(name));
+                    }
+                    Route::Repo(_) => {
+                        self.selected = None;
+                        self.manifest = None;
+                        self.config = None;
+                    }
+                    Route::Tag(name, tag) => {
+                        if shown.as_ref() != Some(&name) {
+                            self.update(Msg::GetImage(name.clone()));
+                        }
+                        if self.selected != Some((name.clone(), tag.clone())) {
+                            self.update(Msg::GetManifest(name, tag));
+                        }
+                    }
+                    Route::Compare(name, tag, other_name, other_tag) => {
+                        if shown.as_ref() != Some(&name) {
+                            self.update(Msg::GetImage(name.clone()));
+                        }
+                        let compared = (name, tag, other_name, other_tag);
+                        if self.compared.as_ref() != Some(&compared) {
+                            let (name, tag, other_name, other_tag) = compared;
+                            self.update(Msg::GetDiff(name, tag, other_name, other_tag));
+                        }
+                    }
+                }
+                true
+            }
             Msg::GetList => {
+                self.failure = None;
                 self.list = None;
+                self.selected = None;
                 self.tags = None;
                 self.manifest = None;
-                let request = match Request::get("http://127.0.0.1:8081/v2/catalog/1")
-                    .body(Nothing) {
-                        Ok(r) => r,
-                        Err(e) => {ConsoleService::error(&format!("failed to initialize request: {}", e)); return false},
-                    };
-                let callback =
-                    self.link
-                        .callback(|response: Response<Json<Result<Repos, anyhow::Error>>>| {
-                            let Json(data) = response.into_body();
-                            Msg::ReceiveResponse(data)
-                        });
-                self.task =
-                    Some(FetchService::fetch(request, callback).expect("failed to start request"));
+                self.config = None;
+                self.search.clear();
+                self.searched.clear();
+                self.update(Msg::GetPage(1));
+                self.navigate(Route::Home, false);
                 true
             }
+            Msg::SearchInput(search) => {
+                self.search = search;
+                // wait for the user to stop typing before querying the backend
+                self.search_timeout = Some(TimeoutService::spawn(
+                    Duration::from_millis(300),
+                    self.link.callback(|_| Msg::Search),
+                ));
+                false
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                let search = self.search.trim().to_string();
+                // refining a search replaces it in the history
+                let replace = !self.searched.is_empty();
+                match search.is_empty() {
+                    true => self.navigate(Route::Home, replace),
+                    false => self.navigate(Route::Search(search), replace),
+                }
+            }
+            Msg::GetPage(page) => {
+                if self.registry.is_empty() {
+                    return false;
+                }
+                let registry = self.registry.clone();
+                let search = self.searched.clone();
+                let task = match search.is_empty() {
+                    true => self.fetch(
+                        &ListImagesPage {
+                            registry,
+                            page,
+                            page_size: None,
+                        },
+                        Msg::ReceiveResponse,
+                    ),
+                    false => self.fetch(
+                        &SearchImages {
+                            registry,
+                            q: Some(search),
+                            page: Some(page),
+                            ..Default::default()
+                        },
+                        Msg::ReceiveResponse,
+                    ),
+                };
+                self.task = Some(task);
+                false
+            }
             Msg::GetImage(img) => {
-                let img = img.replace("/", "%2F");
+                self.failure = None;
+                self.selected = None;
                 self.tags = None;
+                self.size_tasks.clear();
+                self.size_queue.clear();
+                self.sizes.clear();
                 self.manifest = None;
-                let request = Request::get(format!("http://127.0.0.1:8081/v2/tags/{}", img))
-                    .body(Nothing)
-                    .expect("Could not build request");
-                let callback =
-                    self.link
-                        .callback(|response: Response<Json<Result<Tags, anyhow::Error>>>| {
-                            let Json(data) = response.into_body();
-                            Msg::ReceiveResponseTags(data)
-                        });
-                self.task =
-                    Some(FetchService::fetch(request, callback).expect("failed to start request"));
+                self.config = None;
+                let endpoint = ListTags {
+                    registry: self.registry.clone(),
+                    image: img,
+                };
+                self.tags_task = Some(self.fetch(&endpoint, Msg::ReceiveResponseTags));
                 true
             }
             Msg::GetManifest(img, tag) => {
-                let img = img.replace("/", "%2F");
+                self.failure = None;
+                self.selected = Some((img.clone(), tag.clone()));
+                self.retention = None;
                 self.manifest = None;
-                let request =
-                    Request::get(format!("http://127.0.0.1:8081/v2/manifest/{}:{}", img, tag))
-                        .body(Nothing)
-                        .expect("Could not build request");
+                self.digest = None;
+                self.error = None;
+                self.config = None;
+                let request = self.request(&GetManifest {
+                    registry: self.registry.clone(),
+                    image: img.clone(),
+                    reference: tag.clone(),
+                });
                 let callback =
                     self.link
-                        .callback(
-                            |response: Response<Result<String, anyhow::Error>>| match response
+                        .callback(|response: Response<Result<String, anyhow::Error>>| {
+                            let digest = response
+                                .headers()
+                                .get("Docker-Content-Digest")
+                                .and_then(|digest| digest.to_str().ok())
+                                .map(String::from);
+                            let status = response.status().as_u16();
+                            let manifest = response
                                 .into_body()
-                            {
-                                Ok(data) => Msg::ReceiveResponseManifest(get_manifest(&data)),
-                                Err(e) => {
-                                    ConsoleService::error(&format!(
-                                        "failed to parse response body: {}",
-                                        e
-                                    ));
-                                    Msg::Error
-                                }
-                            },
-                        );
-                self.task =
+                                .and_then(|body| Ok(GetManifest::parse(status, &body)?));
+                            Msg::ReceiveResponseManifest(manifest, digest)
+                        });
+                self.manifest_task =
                     Some(FetchService::fetch(request, callback).expect("failed to start request"));
                 true
             }
-            Msg::ReceiveResponse(response) => {
-                if let Ok(res) = response {
+            Msg::ReceiveResponse(response) => match response {
+                Ok(res) => {
                     self.list = Some(res);
-                    return true;
+                    true
+                }
+                Err(e) => self.update(Msg::Failure(format!("Failed to get images: {}", e))),
+            },
+            Msg::ReceiveResponseTags(response) => {
+                let res = match response {
+                    Ok(res) => res,
+                    Err(e) => {
+                        return self.update(Msg::Failure(format!("Failed to get tags: {}", e)))
+                    }
                 };
+                self.size_queue = res
+                    .tags
+                    .iter()
+                    .map(|tag| (res.name.clone(), tag.clone()))
+                    .collect();
+                self.fetch_sizes();
+                self.tags = Some(res);
+                true
+            }
+            Msg::ReceiveResponseSize(tag, response) => {
+                self.size_tasks.remove(&tag);
+                self.fetch_sizes();
+                match response {
+                    Ok(res) => {
+                        self.sizes.insert(tag, res);
+                        true
+                    }
+                    Err(e) => {
+                        ConsoleService::error(&format!("failed to get size of {}: {}", tag, e));
+                        false
+                    }
+                }
+            }
+            Msg::CompareInput(compare) => {
+                self.compare = compare;
                 false
             }
-            Msg::ReceiveResponseTags(response) => {
-                if let Ok(res) = response {
-                    self.tags = Some(res);
-                    return true;
+            Msg::Compare => {
+                let (img, tag) = match &self.selected {
+                    Some(selected) => selected.clone(),
+                    None => return false,
                 };
-                false
+                let other = self.compare.trim();
+                // a bare tag or digest is looked up in the selected image
+                let (other_img, other_tag) = match other.split_once('@') {
+                    Some((other_img, digest)) => (other_img, digest),
+                    None if other.starts_with("sha256:") => (img.as_str(), other),
+                    None => other.split_once(':').unwrap_or((img.as_str(), other)),
+                };
+                if other_tag.is_empty() {
+                    return false;
+                }
+                let route = Route::Compare(
+                    img.clone(),
+                    tag.clone(),
+                    other_img.to_string(),
+                    other_tag.to_string(),
+                );
+                self.navigate(route, false)
+            }
+            Msg::GetDiff(img, tag, other_img, other_tag) => {
+                self.failure = None;
+                self.diff = None;
+                self.compared = Some((
+                    img.clone(),
+                    tag.clone(),
+                    other_img.clone(),
+                    other_tag.clone(),
+                ));
+                let endpoint = CompareImages {
+                    registry: self.registry.clone(),
+                    image: img,
+                    reference: tag,
+                    other_image: Some(other_img),
+                    other_reference: other_tag,
+                    platform: None,
+                };
+                self.diff_task = Some(self.fetch(&endpoint, |response| {
+                    Msg::ReceiveResponseDiff(Box::new(response))
+                }));
+                true
             }
-            Msg::ReceiveResponseManifest(response) => match response {
+            Msg::ReceiveResponseDiff(response) => match *response {
                 Ok(res) => {
+                    self.diff = Some(res);
+                    true
+                }
+                Err(e) => self.update(Msg::Failure(format!("Failed to compare images: {}", e))),
+            },
+            Msg::ReceiveResponseManifest(response, digest) => match response {
+                Ok(res) => {
+                    // schema 1 manifests have no config to ask for and lists are answered
+                    // with the one of their first platform, artifact configs that are not
+                    // image configs fail and are shown in place of the config
+                    if let (Some((img, tag)), true) = (
+                        &self.selected,
+                        res.config_digest().is_some() || res.platforms().is_some(),
+                    ) {
+                        let endpoint = GetConfig {
+                            registry: self.registry.clone(),
+                            image: img.clone(),
+                            reference: tag.clone(),
+                        };
+                        self.config_task = Some(self.fetch(&endpoint, |response| {
+                            Msg::ReceiveResponseConfig(Box::new(response))
+                        }));
+                    }
                     self.manifest = Some(res);
+                    self.digest = digest;
                     return true;
                 }
                 Err(e) => {
@@ -211,6 +1028,127 @@ impl Component for Model {
                     return true;
                 }
             },
+            Msg::ReceiveResponseConfig(response) => {
+                self.config = Some(response.map_err(|e| e.to_string()));
+                true
+            }
+            Msg::DeleteTag(force) => {
+                let (img, tag) = match &self.selected {
+                    Some(selected) => selected.clone(),
+                    None => return false,
+                };
+                if !force && !DialogService::confirm(&format!("Delete {}:{} ?", img, tag)) {
+                    return false;
+                }
+                let token = match self.delete_token.clone().or_else(|| {
+                    DialogService::prompt("Events token of the api, to delete tags", None)
+                }) {
+                    Some(token) => token,
+                    None => return false,
+                };
+                let mut request = self.request(&DeleteManifest {
+                    registry: self.registry.clone(),
+                    image: img,
+                    reference: tag,
+                    force: Some(force),
+                });
+                match format!("Bearer {}", token).parse() {
+                    Ok(value) => request.headers_mut().insert("Authorization", value),
+                    Err(_) => {
+                        DialogService::alert("The token can only hold visible characters");
+                        return false;
+                    }
+                };
+                self.delete_token = Some(token);
+                let callback =
+                    self.link
+                        .callback(|response: Response<Result<String, anyhow::Error>>| {
+                            let status = response.status().as_u16();
+                            match response.into_body() {
+                                // the other tags pointing to the digest are listed
+                                Ok(body) if status == 409 => {
+                                    match serde_json::from_str::<DeleteReport>(&body) {
+                                        Ok(report) => Msg::ConfirmDelete(report),
+                                        Err(e) => Msg::ReceiveResponseDelete(Err(e.into())),
+                                    }
+                                }
+                                Ok(body) => Msg::ReceiveResponseDelete(
+                                    DeleteManifest::parse(status, &body).map_err(Into::into),
+                                ),
+                                Err(e) => Msg::ReceiveResponseDelete(Err(e)),
+                            }
+                        });
+                self.task =
+                    Some(FetchService::fetch(request, callback).expect("failed to start request"));
+                false
+            }
+            Msg::ConfirmDelete(report)
+                if DialogService::confirm(&format!(
+                    "{} is shared by tags {}, delete all of them ?",
+                    report.digest,
+                    report.tags.join(", ")
+                )) =>
+            {
+                self.update(Msg::DeleteTag(true))
+            }
+            Msg::ReceiveResponseDelete(response) => match response {
+                Ok(report) => {
+                    if let Some(tags) = self.tags.as_mut() {
+                        tags.tags.retain(|t| !report.tags.contains(t));
+                    }
+                    let img = match self.selected.take() {
+                        Some((img, _)) => img,
+                        None => return true,
+                    };
+                    if report.removed_from_catalog {
+                        if let Some(list) = self.list.as_mut() {
+                            list.repositories.retain(|r| *r != img);
+                            list.total = list.total.saturating_sub(1);
+                        }
+                    }
+                    self.manifest = None;
+                    self.config = None;
+                    // the link to the deleted tag leads nowhere now
+                    self.navigate(Route::Repo(img), true);
+                    true
+                }
+                Err(e) => {
+                    // the token may have been refused, it is asked again next time
+                    self.delete_token = None;
+                    DialogService::alert(&format!("Failed to delete tag: {}", e));
+                    false
+                }
+            },
+            Msg::GetRetention => {
+                self.failure = None;
+                self.selected = None;
+                self.manifest = None;
+                self.config = None;
+                let endpoint = GetRetentionReport {
+                    registry: self.registry.clone(),
+                };
+                self.task = Some(self.fetch(&endpoint, Msg::ReceiveResponseRetention));
+                true
+            }
+            Msg::ReceiveResponseRetention(response) => match response {
+                Ok(res) => {
+                    self.retention = Some(res);
+                    true
+                }
+                Err(e) => self.update(Msg::Failure(format!(
+                    "Failed to get retention report: {}",
+                    e
+                ))),
+            },
+            Msg::Failure(failure) => {
+                ConsoleService::error(&failure);
+                self.failure = Some(failure);
+                true
+            }
+            Msg::DismissFailure => {
+                self.failure = None;
+                true
+            }
             _ => false,
         }
     }
@@ -221,11 +1159,24 @@ impl Component for Model {
 
     fn view(&self) -> Html {
         html! {
+            <>
+            {self.view_registries()}
+            {self.view_failure()}
+            <button onclick=self.link.callback(|_| Msg::GetRetention)>
+                { "Retention report" }
+            </button>
             <div class="flexWrap">
-                <div class="flexCol scroll">{self.view_image_list()}</div>
+                <div class="flexCol scroll">{self.view_search()}{self.view_pages()}{self.view_image_list()}</div>
                 <div class="flexCol scroll">{self.view_tags()}</div>
-                <div class="flexCol scroll_manifest">{self.view_infos()}</div>
+                <div class="flexCol scroll_manifest">
+                    { match self.compared {
+                        Some(_) => self.view_diff(),
+                        None => html! {<>{self.view_delete()}{self.view_compare()}{self.view_infos()}{self.view_config()}{self.view_dockerfile()}</>},
+                    } }
+                    {self.view_retention()}
+                </div>
             </div>
+            </>
         }
     }
 }
@@ -0,0 +1,160 @@
+use shipyard::{url_decode, url_encode};
+
+/// what is shown, kept in the fragment of the url so that it can be shared and
+/// browsed with back and forward
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// `#/`
+    Home,
+    /// `#/repo/{name}`
+    Repo(String),
+    /// `#/repo/{name}/tag/{tag}`
+    Tag(String, String),
+    /// `#/repo/{name}/tag/{tag}/compare/{other_name}/{other_tag}`
+    Compare(String, String, String, String),
+    /// `#/search?q={q}`
+    Search(String),
+}
+
+impl Route {
+    /// route of a fragment and the registry of its `registry` parameter, unknown
+    /// fragments lead home
+    pub fn parse(hash: &str) -> (Route, Option<String>) {
+        let hash = hash.trim_start_matches('#');
+        let (path, query) = hash.split_once('?').unwrap_or((hash, ""));
+        let param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|p| p.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| url_decode(value))
+        };
+        // names are encoded, so their slashes do not split segments
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(url_decode)
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let route = match segments.as_slice() {
+            ["repo", name] => Route::Repo(name.to_string()),
+            ["repo", name, "tag", tag] => Route::Tag(name.to_string(), tag.to_string()),
+            ["repo", name, "tag", tag, "compare", other_name, other_tag] => Route::Compare(
+                name.to_string(),
+                tag.to_string(),
+                other_name.to_string(),
+                other_tag.to_string(),
+            ),
+            ["search"] => Route::Search(param("q").unwrap_or_default()),
+            _ => Route::Home,
+        };
+        (route, param("registry").filter(|r| !r.is_empty()))
+    }
+
+    /// fragment of the route in `registry`
+    pub fn to_hash(&self, registry: &str) -> String {
+        let hash = match self {
+            Route::Home => "#/".to_string(),
+            Route::Repo(name) => format!("#/repo/{}", url_encode(name)),
+            Route::Tag(name, tag) => format!("#/repo/{}/tag/{}", url_encode(name), url_encode(tag)),
+            Route::Compare(name, tag, other_name, other_tag) => format!(
+                "#/repo/{}/tag/{}/compare/{}/{}",
+                url_encode(name),
+                url_encode(tag),
+                url_encode(other_name),
+                url_encode(other_tag)
+            ),
+            Route::Search(q) => format!("#/search?q={}", url_encode(q)),
+        };
+        match (registry.is_empty(), hash.contains('?')) {
+            (true, _) => hash,
+            (false, true) => format!("{}&registry={}", hash, url_encode(registry)),
+            (false, false) => format!("{}?registry={}", hash, url_encode(registry)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(hash: &str) -> Route {
+        Route::parse(hash).0
+    }
+
+    #[test]
+    fn parses_namespaced_images() {
+        let route = Route::Tag("team/app".to_string(), "1.0".to_string());
+        let hash = route.to_hash("");
+        assert_eq!(hash, "#/repo/team%2Fapp/tag/1.0");
+        assert_eq!(parsed(&hash), route);
+        assert_eq!(
+            parsed("#/repo/team%2Fapp"),
+            Route::Repo("team/app".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_digests_as_references() {
+        let digest = "sha256:0123abcd";
+        let route = Route::Tag("app".to_string(), digest.to_string());
+        let hash = route.to_hash("");
+        assert_eq!(hash, "#/repo/app/tag/sha256%3A0123abcd");
+        assert_eq!(parsed(&hash), route);
+        assert_eq!(parsed("#/repo/app/tag/sha256:0123abcd"), route);
+    }
+
+    #[test]
+    fn parses_comparisons() {
+        let route = Route::Compare(
+            "app".to_string(),
+            "latest".to_string(),
+            "team/app".to_string(),
+            "sha256:0123abcd".to_string(),
+        );
+        let hash = route.to_hash("");
+        assert_eq!(
+            hash,
+            "#/repo/app/tag/latest/compare/team%2Fapp/sha256%3A0123abcd"
+        );
+        assert_eq!(parsed(&hash), route);
+    }
+
+    #[test]
+    fn parses_encoded_search_queries() {
+        let route = Route::Search("a b&c=d/é".to_string());
+        let hash = route.to_hash("hub");
+        assert_eq!(parsed(&hash), route);
+        assert_eq!(Route::parse(&hash).1, Some("hub".to_string()));
+        assert_eq!(
+            parsed("#/search?q=team%2Fapp+x"),
+            Route::Search("team/app+x".to_string())
+        );
+        assert_eq!(parsed("#/search"), Route::Search(String::new()));
+    }
+
+    #[test]
+    fn keeps_the_registry() {
+        assert_eq!(Route::Home.to_hash("hub"), "#/?registry=hub");
+        assert_eq!(
+            Route::parse("#/repo/app?registry=hub"),
+            (Route::Repo("app".to_string()), Some("hub".to_string()))
+        );
+        assert_eq!(Route::parse("#/?registry="), (Route::Home, None));
+    }
+
+    #[test]
+    fn leads_unknown_fragments_home() {
+        for hash in [
+            "",
+            "#",
+            "#/",
+            "#/unknown",
+            "#/repo",
+            "#/repo/app/tag",
+            "#/repo/a/b/c",
+        ] {
+            assert_eq!(parsed(hash), Route::Home, "{}", hash);
+        }
+    }
+}
@@ -0,0 +1,690 @@
+//! Typed requests to the backend routes, and the OpenAPI document describing them
+
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    api_error, get_manifest, parse_json, url_encode, ApiError, CacheStats, CatalogPage,
+    ClientConfig, DeleteReport, DockerManifest, EventEnvelope, ImageConfig, ImageDiff, ImageSize,
+    Manifest, ManifestV2, ManifestV2List, OciImageIndex, OciImageManifest, Readiness, RefreshJob,
+    RefreshStatus, RegistryEvent, RetentionReport, Tags,
+};
+
+///http method of an endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    ///`GET`
+    Get,
+    ///`POST`
+    Post,
+    ///`DELETE`
+    Delete,
+}
+
+impl Method {
+    ///name of the method, e.g. `GET`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+///body answered by an endpoint on success
+pub trait Body: Sized {
+    ///parses the body of a successful answer
+    fn from_body(body: &str) -> Result<Self, ApiError>;
+    ///media type and schema of the body in the OpenAPI document
+    fn content(gen: &mut SchemaGenerator) -> (&'static str, Schema);
+}
+
+impl<T: DeserializeOwned + JsonSchema> Body for T {
+    fn from_body(body: &str) -> Result<Self, ApiError> {
+        parse_json(body)
+    }
+
+    fn content(gen: &mut SchemaGenerator) -> (&'static str, Schema) {
+        ("application/json", gen.subschema_for::<T>())
+    }
+}
+
+///plain text body
+#[derive(Debug, Clone, Default)]
+pub struct Text(pub String);
+
+impl Body for Text {
+    fn from_body(body: &str) -> Result<Self, ApiError> {
+        Ok(Text(body.to_string()))
+    }
+
+    fn content(gen: &mut SchemaGenerator) -> (&'static str, Schema) {
+        ("text/plain", gen.subschema_for::<String>())
+    }
+}
+
+///manifests are answered as the registry sent them, whatever their schema
+impl Body for DockerManifest {
+    fn from_body(body: &str) -> Result<Self, ApiError> {
+        Ok(get_manifest(body)?)
+    }
+
+    fn content(gen: &mut SchemaGenerator) -> (&'static str, Schema) {
+        let schema = SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(vec![
+                    gen.subschema_for::<Manifest>(),
+                    gen.subschema_for::<ManifestV2>(),
+                    gen.subschema_for::<ManifestV2List>(),
+                    gen.subschema_for::<OciImageManifest>(),
+                    gen.subschema_for::<OciImageIndex>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        ("application/json", schema.into())
+    }
+}
+
+///route of the backend
+///
+///the fields of the implementing struct are the parameters of the route, those named in
+///[`Endpoint::PATH`] go in the path and the others in the query
+pub trait Endpoint: Serialize + JsonSchema {
+    ///body answered on success
+    type Response: Body;
+    ///method of the route
+    const METHOD: Method;
+    ///path of the route, parameters between braces
+    const PATH: &'static str;
+    ///name of the backend handler, used as operation id
+    const OPERATION: &'static str;
+    ///one line description of the route
+    const SUMMARY: &'static str;
+    ///status answered on success
+    const STATUS: u16 = 200;
+    ///headers of the success answer, as name and description
+    const HEADERS: &'static [(&'static str, &'static str)] = &[];
+
+    ///path and query of the request, to be appended to the address of the backend
+    fn url(&self) -> String {
+        let mut path = Self::PATH.to_string();
+        let mut query = vec![];
+        if let Ok(Value::Object(params)) = serde_json::to_value(self) {
+            for (name, value) in params {
+                let value = match value {
+                    Value::Null => continue,
+                    Value::String(s) => s,
+                    v => v.to_string(),
+                };
+                let template = format!("{{{}}}", name);
+                match path.contains(&template) {
+                    true => path = path.replace(&template, &url_encode(&value)),
+                    false => query.push(format!("{}={}", name, url_encode(&value))),
+                }
+            }
+        }
+        match query.is_empty() {
+            true => path,
+            false => format!("{}?{}", path, query.join("&")),
+        }
+    }
+
+    ///parses an answer of the route, or its [`ApiError`] when the status is not a success
+    fn parse(status: u16, body: &str) -> Result<Self::Response, ApiError> {
+        match (200..300).contains(&status) {
+            true => Self::Response::from_body(body),
+            false => Err(api_error(status, body)),
+        }
+    }
+
+    ///answers other than the success and the [`ApiError`]s, as status, description and schema
+    fn other_responses(_gen: &mut SchemaGenerator) -> Vec<(u16, &'static str, Schema)> {
+        vec![]
+    }
+
+    ///schema of the json body of the request, `None` when it takes none
+    fn request_body(_gen: &mut SchemaGenerator) -> Option<Schema> {
+        None
+    }
+}
+
+///`GET /healthz`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct Healthz;
+
+impl Endpoint for Healthz {
+    type Response = Text;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/healthz";
+    const OPERATION: &'static str = "healthz";
+    const SUMMARY: &'static str = "Answers as long as the process is up";
+}
+
+///`GET /readyz`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct Readyz;
+
+impl Endpoint for Readyz {
+    type Response = Readiness;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/readyz";
+    const OPERATION: &'static str = "readyz";
+    const SUMMARY: &'static str = "Readiness of the storage and of every registry";
+
+    fn other_responses(gen: &mut SchemaGenerator) -> Vec<(u16, &'static str, Schema)> {
+        vec![(503, "Not ready", gen.subschema_for::<Readiness>())]
+    }
+}
+
+///`GET /metrics`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetMetrics;
+
+impl Endpoint for GetMetrics {
+    type Response = Text;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/metrics";
+    const OPERATION: &'static str = "get_metrics";
+    const SUMMARY: &'static str = "Metrics in the Prometheus text format";
+}
+
+///`GET /openapi.json`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetOpenApi;
+
+impl Endpoint for GetOpenApi {
+    type Response = Value;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/openapi.json";
+    const OPERATION: &'static str = "get_openapi";
+    const SUMMARY: &'static str = "This document";
+}
+
+///`GET /config.json`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetClientConfig;
+
+impl Endpoint for GetClientConfig {
+    type Response = ClientConfig;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/config.json";
+    const OPERATION: &'static str = "client_config";
+    const SUMMARY: &'static str = "Where the frontend finds the api";
+}
+
+///`GET /v2/registries`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ListRegistries;
+
+impl Endpoint for ListRegistries {
+    type Response = Vec<String>;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/registries";
+    const OPERATION: &'static str = "list_registries";
+    const SUMMARY: &'static str = "Names of the configured registries";
+}
+
+///`GET /v2/cache`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetCacheStats;
+
+impl Endpoint for GetCacheStats {
+    type Response = CacheStats;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/cache";
+    const OPERATION: &'static str = "cache_stats";
+    const SUMMARY: &'static str = "Hits and misses of the caches since startup";
+}
+
+///`GET /v2/{registry}/catalog/{page}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ListImagesPage {
+    ///name of the registry
+    pub registry: String,
+    ///number of the page, starting at 1
+    pub page: usize,
+    ///images per page, the configured page size by default
+    pub page_size: Option<usize>,
+}
+
+impl Endpoint for ListImagesPage {
+    type Response = CatalogPage;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/catalog/{page}";
+    const OPERATION: &'static str = "list_images_page";
+    const SUMMARY: &'static str = "Page of the catalog by number";
+}
+
+///`GET /v2/{registry}/catalog`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ListImagesCursor {
+    ///name of the registry
+    pub registry: String,
+    ///images per page, the configured page size by default
+    pub n: Option<usize>,
+    ///image the page starts after, the `next` of the previous page
+    pub last: Option<String>,
+}
+
+impl Endpoint for ListImagesCursor {
+    type Response = CatalogPage;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/catalog";
+    const OPERATION: &'static str = "list_images_cursor";
+    const SUMMARY: &'static str = "Page of the catalog following a cursor";
+}
+
+///`GET /v2/{registry}/search`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct SearchImages {
+    ///name of the registry
+    pub registry: String,
+    ///text the image names contain, ignoring case
+    pub q: Option<String>,
+    ///namespace the image names start with
+    pub namespace: Option<String>,
+    ///prefix of the image names, after the namespace
+    pub prefix: Option<String>,
+    ///number of the page, starting at 1
+    pub page: Option<usize>,
+    ///images per page, the configured page size by default
+    pub page_size: Option<usize>,
+}
+
+impl Endpoint for SearchImages {
+    type Response = CatalogPage;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/search";
+    const OPERATION: &'static str = "search_images";
+    const SUMMARY: &'static str = "Page of the images matching a search";
+}
+
+///`GET /v2/{registry}/refresh_catalog`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct RefreshCatalog {
+    ///name of the registry
+    pub registry: String,
+}
+
+impl Endpoint for RefreshCatalog {
+    type Response = RefreshJob;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/refresh_catalog";
+    const OPERATION: &'static str = "refresh_catalog";
+    const SUMMARY: &'static str = "Starts a refresh of the catalog unless one is running";
+    const STATUS: u16 = 202;
+}
+
+///`GET /v2/{registry}/refresh_status`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetRefreshStatus {
+    ///name of the registry
+    pub registry: String,
+}
+
+impl Endpoint for GetRefreshStatus {
+    type Response = RefreshStatus;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/refresh_status";
+    const OPERATION: &'static str = "refresh_status";
+    const SUMMARY: &'static str = "State of the catalog refreshes";
+}
+
+///`GET /v2/{registry}/tags/{image}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ListTags {
+    ///name of the registry
+    pub registry: String,
+    ///name of the image
+    pub image: String,
+}
+
+impl Endpoint for ListTags {
+    type Response = Tags;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/tags/{image}";
+    const OPERATION: &'static str = "list_tags";
+    const SUMMARY: &'static str = "Tags of an image";
+}
+
+///`GET /v2/{registry}/manifest/{image}:{reference}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetManifest {
+    ///name of the registry
+    pub registry: String,
+    ///name of the image
+    pub image: String,
+    ///tag or digest
+    pub reference: String,
+}
+
+impl Endpoint for GetManifest {
+    type Response = DockerManifest;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/manifest/{image}:{reference}";
+    const OPERATION: &'static str = "get_manifest";
+    const SUMMARY: &'static str = "Manifest of a tag or digest, as sent by the registry";
+    const HEADERS: &'static [(&'static str, &'static str)] =
+        &[("Docker-Content-Digest", "Digest of the manifest")];
+}
+
+///`GET /v2/{registry}/config/{image}:{reference}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetConfig {
+    ///name of the registry
+    pub registry: String,
+    ///name of the image
+    pub image: String,
+    ///tag or digest
+    pub reference: String,
+}
+
+impl Endpoint for GetConfig {
+    type Response = ImageConfig;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/config/{image}:{reference}";
+    const OPERATION: &'static str = "get_config";
+    const SUMMARY: &'static str = "Image config, of the first platform for manifest lists";
+}
+
+///`GET /v2/{registry}/size/{image}:{reference}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetSize {
+    ///name of the registry
+    pub registry: String,
+    ///name of the image
+    pub image: String,
+    ///tag or digest
+    pub reference: String,
+}
+
+impl Endpoint for GetSize {
+    type Response = ImageSize;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/size/{image}:{reference}";
+    const OPERATION: &'static str = "get_size";
+    const SUMMARY: &'static str = "Compressed size of a tag and of each of its platforms";
+}
+
+///`GET /v2/{registry}/compare/{image}:{reference}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct CompareImages {
+    ///name of the registry
+    pub registry: String,
+    ///name of the base image
+    pub image: String,
+    ///tag or digest of the base image
+    pub reference: String,
+    ///name of the target image, the base one when unset
+    pub other_image: Option<String>,
+    ///tag or digest of the target image
+    pub other_reference: String,
+    ///platform compared when a reference is a manifest list, e.g. `linux/arm64/v8`,
+    ///the first runnable one of the base image when unset
+    pub platform: Option<String>,
+}
+
+impl Endpoint for CompareImages {
+    type Response = ImageDiff;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/compare/{image}:{reference}";
+    const OPERATION: &'static str = "compare_images";
+    const SUMMARY: &'static str =
+        "Layers, size, config, history and platforms changed between two tags or digests";
+}
+
+///`DELETE /v2/{registry}/manifest/{image}:{reference}`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct DeleteManifest {
+    ///name of the registry
+    pub registry: String,
+    ///name of the image
+    pub image: String,
+    ///tag or digest
+    pub reference: String,
+    ///delete even when other tags point to the same digest
+    pub force: Option<bool>,
+}
+
+impl Endpoint for DeleteManifest {
+    type Response = DeleteReport;
+    const METHOD: Method = Method::Delete;
+    const PATH: &'static str = "/v2/{registry}/manifest/{image}:{reference}";
+    const OPERATION: &'static str = "delete_manifest";
+    const SUMMARY: &'static str =
+        "Deletes the manifest a tag points to, authorized by the events token";
+
+    fn other_responses(gen: &mut SchemaGenerator) -> Vec<(u16, &'static str, Schema)> {
+        vec![(
+            409,
+            "Other tags point to the same digest, nothing was deleted",
+            gen.subschema_for::<DeleteReport>(),
+        )]
+    }
+}
+
+///`POST /v2/{registry}/events`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ReceiveEvents {
+    ///name of the registry
+    pub registry: String,
+}
+
+impl Endpoint for ReceiveEvents {
+    type Response = Text;
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/v2/{registry}/events";
+    const OPERATION: &'static str = "receive_events";
+    const SUMMARY: &'static str =
+        "Notifications of the registry, authorized by the events token as a bearer";
+
+    fn request_body(gen: &mut SchemaGenerator) -> Option<Schema> {
+        Some(gen.subschema_for::<EventEnvelope>())
+    }
+}
+
+///`GET /v2/{registry}/activity`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ListActivity {
+    ///name of the registry
+    pub registry: String,
+}
+
+impl Endpoint for ListActivity {
+    type Response = Vec<RegistryEvent>;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/activity";
+    const OPERATION: &'static str = "list_activity";
+    const SUMMARY: &'static str = "Latest events received from the registry, newest first";
+}
+
+///`GET /v2/{registry}/retention/report`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct GetRetentionReport {
+    ///name of the registry
+    pub registry: String,
+}
+
+impl Endpoint for GetRetentionReport {
+    type Response = RetentionReport;
+    const METHOD: Method = Method::Get;
+    const PATH: &'static str = "/v2/{registry}/retention/report";
+    const OPERATION: &'static str = "retention_report";
+    const SUMMARY: &'static str = "Tags the retention policies would delete";
+}
+
+///`POST /v2/{registry}/retention/execute`
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct ExecuteRetention {
+    ///name of the registry
+    pub registry: String,
+}
+
+impl Endpoint for ExecuteRetention {
+    type Response = RetentionReport;
+    const METHOD: Method = Method::Post;
+    const PATH: &'static str = "/v2/{registry}/retention/execute";
+    const OPERATION: &'static str = "retention_execute";
+    const SUMMARY: &'static str =
+        "Deletes the tags matched by the retention policies, authorized by the events token";
+}
+
+fn json_content(media_type: &str, schema: Schema) -> Value {
+    json!({ media_type: { "schema": schema } })
+}
+
+///adds the operation of `E` to `paths`
+fn describe<E: Endpoint>(gen: &mut SchemaGenerator, paths: &mut Map<String, Value>) {
+    let mut parameters = vec![];
+    if let Some(object) = E::json_schema(gen).into_object().object {
+        for (name, schema) in object.properties {
+            let mut schema = schema.into_object();
+            let in_path = E::PATH.contains(&format!("{{{}}}", name));
+            let mut parameter = json!({
+                "name": name,
+                "in": if in_path { "path" } else { "query" },
+                "required": in_path || object.required.contains(&name),
+            });
+            if let Some(description) = schema.metadata.as_mut().and_then(|m| m.description.take()) {
+                parameter["description"] = json!(description);
+            }
+            parameter["schema"] = json!(schema);
+            parameters.push(parameter);
+        }
+    }
+    let (media_type, schema) = E::Response::content(gen);
+    let mut success =
+        json!({ "description": "Success", "content": json_content(media_type, schema) });
+    if !E::HEADERS.is_empty() {
+        let headers: Map<String, Value> = E::HEADERS
+            .iter()
+            .map(|(name, description)| {
+                let header = json!({ "description": description, "schema": { "type": "string" } });
+                (name.to_string(), header)
+            })
+            .collect();
+        success["headers"] = Value::Object(headers);
+    }
+    let mut responses = Map::new();
+    responses.insert(E::STATUS.to_string(), success);
+    for (status, description, schema) in E::other_responses(gen) {
+        responses.insert(
+            status.to_string(),
+            json!({
+                "description": description,
+                "content": json_content("application/json", schema),
+            }),
+        );
+    }
+    responses.insert(
+        "default".to_string(),
+        json!({
+            "description": "Error",
+            "content": json_content("application/json", gen.subschema_for::<ApiError>()),
+        }),
+    );
+    let mut operation = json!({
+        "operationId": E::OPERATION,
+        "summary": E::SUMMARY,
+        "parameters": parameters,
+        "responses": responses,
+    });
+    if let Some(schema) = E::request_body(gen) {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": json_content("application/json", schema),
+        });
+    }
+    if let Value::Object(methods) = paths
+        .entry(E::PATH)
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        methods.insert(E::METHOD.as_str().to_lowercase(), operation);
+    }
+}
+
+///OpenAPI 3 document of every route of the backend
+pub fn openapi() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    describe::<Healthz>(&mut gen, &mut paths);
+    describe::<Readyz>(&mut gen, &mut paths);
+    describe::<GetMetrics>(&mut gen, &mut paths);
+    describe::<GetOpenApi>(&mut gen, &mut paths);
+    describe::<GetClientConfig>(&mut gen, &mut paths);
+    describe::<ListRegistries>(&mut gen, &mut paths);
+    describe::<GetCacheStats>(&mut gen, &mut paths);
+    describe::<ListImagesPage>(&mut gen, &mut paths);
+    describe::<ListImagesCursor>(&mut gen, &mut paths);
+    describe::<SearchImages>(&mut gen, &mut paths);
+    describe::<RefreshCatalog>(&mut gen, &mut paths);
+    describe::<GetRefreshStatus>(&mut gen, &mut paths);
+    describe::<ListTags>(&mut gen, &mut paths);
+    describe::<GetManifest>(&mut gen, &mut paths);
+    describe::<GetConfig>(&mut gen, &mut paths);
+    describe::<GetSize>(&mut gen, &mut paths);
+    describe::<CompareImages>(&mut gen, &mut paths);
+    describe::<DeleteManifest>(&mut gen, &mut paths);
+    describe::<ReceiveEvents>(&mut gen, &mut paths);
+    describe::<ListActivity>(&mut gen, &mut paths);
+    describe::<GetRetentionReport>(&mut gen, &mut paths);
+    describe::<ExecuteRetention>(&mut gen, &mut paths);
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "shipyard",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": gen.definitions() },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::url_decode;
+
+    #[test]
+    fn builds_path_and_query() {
+        let endpoint = GetManifest {
+            registry: "hub".to_string(),
+            image: "library/nginx".to_string(),
+            reference: "sha256:abc".to_string(),
+        };
+        assert_eq!(
+            endpoint.url(),
+            "/v2/hub/manifest/library%2Fnginx:sha256%3Aabc"
+        );
+        let endpoint = SearchImages {
+            registry: "hub".to_string(),
+            q: Some("a b&c".to_string()),
+            page: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(endpoint.url(), "/v2/hub/search?page=2&q=a%20b%26c");
+        assert_eq!(ListRegistries.url(), "/v2/registries");
+    }
+
+    #[test]
+    fn round_trips_path_parameters() {
+        for image in ["nginx", "library/nginx", "team/app/worker", "a-b_c.d"] {
+            let endpoint = ListTags {
+                registry: "hub".to_string(),
+                image: image.to_string(),
+            };
+            let url = endpoint.url();
+            let segment = url.strip_prefix("/v2/hub/tags/").unwrap();
+            assert!(!segment.contains('/'));
+            assert_eq!(url_decode(segment), image);
+        }
+    }
+}
@@ -17,7 +17,6 @@ extern crate cached;
 #[cfg(feature = "default")]
 mod backend;
 
-use anyhow::Error;
 #[cfg(feature = "default")]
 pub use backend::server::Server;
 
@@ -34,60 +33,695 @@ mod frontend;
 
 #[cfg(feature = "frontend")]
 pub use frontend::components::root::RootComponent;
+
+pub mod api;
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use crate::DockerManifest::*;
 
+///media type of signed docker schema 1 manifests
+pub const MEDIA_TYPE_DOCKER_V1: &str = "application/vnd.docker.distribution.manifest.v1+prettyjws";
+///media type of docker schema 2 manifests
+pub const MEDIA_TYPE_DOCKER_V2: &str = "application/vnd.docker.distribution.manifest.v2+json";
+///media type of docker schema 2 manifest lists
+pub const MEDIA_TYPE_DOCKER_V2_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+///media type of OCI image manifests
+pub const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+///media type of OCI image indexes
+pub const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+///media types to send in the `Accept` header when requesting a manifest
+pub const MANIFEST_MEDIA_TYPES: [&str; 4] = [
+    MEDIA_TYPE_DOCKER_V2_LIST,
+    MEDIA_TYPE_DOCKER_V2,
+    MEDIA_TYPE_OCI_INDEX,
+    MEDIA_TYPE_OCI_MANIFEST,
+];
+
+///errors returned by [`get_manifest`]
+#[derive(Debug)]
+pub enum ManifestError {
+    ///body is not valid json or does not match the expected manifest shape
+    Parse(serde_json::Error),
+    ///schema version is neither 1 nor 2
+    UnknownSchema(Option<usize>),
+    ///schema 2 manifest with an unsupported media type
+    UnknownMediaType(String),
+    ///registry answered with an error body instead of a manifest
+    Registry(Vec<ErrorsV2>),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Parse(e) => write!(f, "Failed to parse manifest: {}", e),
+            ManifestError::UnknownSchema(Some(v)) => write!(f, "Invalid schema version: {}", v),
+            ManifestError::UnknownSchema(None) => write!(f, "Missing schema version"),
+            ManifestError::UnknownMediaType(m) => write!(f, "Invalid media type: {}", m),
+            ManifestError::Registry(errors) => write!(
+                f,
+                "Registry error: {}",
+                errors
+                    .iter()
+                    .map(|e| format!("{}: {}", e.code, e.message))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ManifestError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ManifestError> for ApiError {
+    fn from(e: ManifestError) -> Self {
+        let (code, errors) = match &e {
+            ManifestError::Registry(errors) => ("upstream_error", errors.clone()),
+            _ => ("invalid_manifest", vec![]),
+        };
+        ApiError {
+            code: code.to_string(),
+            message: e.to_string(),
+            upstream_status: None,
+            errors,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ManifestError {
+    fn from(e: serde_json::Error) -> Self {
+        ManifestError::Parse(e)
+    }
+}
+
+/// struct to parse the error answered by every backend route to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ApiError {
+    /// kind of error, e.g. `bad_request`, `not_found` or `upstream_error`
+    pub code: String,
+    /// human readable message
+    pub message: String,
+    /// status answered by the registry when the error comes from it
+    pub upstream_status: Option<u16>,
+    /// errors detailed by the registry, empty when it gave none
+    pub errors: Vec<ErrorsV2>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.errors.is_empty() {
+            write!(
+                f,
+                " ({})",
+                self.errors
+                    .iter()
+                    .map(|e| format!("{}: {}", e.code, e.message))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+///error of a backend answer with a non success status, from its body when it is an [`ApiError`]
+pub fn api_error(status: u16, body: &str) -> ApiError {
+    serde_json::from_str(body).unwrap_or_else(|_| ApiError {
+        code: "unknown".to_string(),
+        message: format!("Backend answered {}: {}", status, body),
+        upstream_status: None,
+        errors: vec![],
+    })
+}
+
+///parses the body of a backend answer, or its [`ApiError`] when the status is not a success
+pub fn parse_response<T: serde::de::DeserializeOwned>(
+    status: u16,
+    body: &str,
+) -> Result<T, ApiError> {
+    if !(200..300).contains(&status) {
+        return Err(api_error(status, body));
+    }
+    parse_json(body)
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, ApiError> {
+    serde_json::from_str(body).map_err(|e| ApiError {
+        code: "invalid_response".to_string(),
+        message: format!("Failed to parse response: {}", e),
+        upstream_status: None,
+        errors: vec![],
+    })
+}
+
 ///docker manifest parser
-pub fn get_manifest(manifest: &str) -> Result<DockerManifest, anyhow::Error> {
-    let config: SchemaVersion = serde_json::from_str(manifest).expect("Failed to parse manifest");
+pub fn get_manifest(manifest: &str) -> Result<DockerManifest, ManifestError> {
+    let config: SchemaVersion = serde_json::from_str(manifest)?;
+    if let Some(errors) = config.errors {
+        return Err(ManifestError::Registry(errors));
+    }
     match (config.schema_version, config.media_type) {
-        (1, _) => Ok(V1(serde_json::from_str(manifest).expect("Failed to parse manifest"))),
-        (2, Some(media_type)) => match media_type.as_str() {
-            "application/vnd.docker.distribution.manifest.list.v2+json" => Ok(V2List(serde_json::from_str(manifest).expect("Failed to parse manifest"))),
-            "application/vnd.docker.distribution.manifest.v2+json" => Ok(V2(serde_json::from_str(manifest).expect("Failed to parse manifest"))),
-            _ => Err(Error::msg("Invalid media type"))
+        (Some(1), _) => Ok(V1(serde_json::from_str(manifest)?)),
+        (Some(2), Some(media_type)) => match media_type.as_str() {
+            MEDIA_TYPE_DOCKER_V2_LIST => Ok(V2List(serde_json::from_str(manifest)?)),
+            MEDIA_TYPE_DOCKER_V2 => Ok(V2(serde_json::from_str(manifest)?)),
+            MEDIA_TYPE_OCI_INDEX => Ok(OciIndex(serde_json::from_str(manifest)?)),
+            MEDIA_TYPE_OCI_MANIFEST => Ok(OciManifest(serde_json::from_str(manifest)?)),
+            _ => Err(ManifestError::UnknownMediaType(media_type)),
         },
-        (_, _) => Err(Error::msg("Invalid schema version")),
+        // mediaType is optional in the OCI spec, tell index and manifest apart by their content
+        (Some(2), None) => match config.manifests {
+            Some(_) => Ok(OciIndex(serde_json::from_str(manifest)?)),
+            None => Ok(OciManifest(serde_json::from_str(manifest)?)),
+        },
+        (version, _) => Err(ManifestError::UnknownSchema(version)),
     }
 }
 
 /// struct to parse `/catalog` requests to
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 pub struct Repos {
     /// list of image names
     pub repositories: Vec<String>,
 }
 
+/// struct to parse `/catalog` and `/search` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct CatalogPage {
+    /// image names of the page, in alphabetical order
+    pub repositories: Vec<String>,
+    /// number of the page, starting at 1
+    pub page: usize,
+    /// maximum number of images per page
+    pub page_size: usize,
+    /// number of images matching the request over all pages
+    pub total: usize,
+    /// cursor to send as `last` for the following page, `None` on the last page
+    pub next: Option<String>,
+}
+
+///percent-encodes a string to be used as a query parameter or path segment
+pub fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+///decodes a string percent-encoded by [`url_encode`] or a browser
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+///formats a size in bytes with a binary unit, e.g. `12.3 MiB`
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+/// struct to parse `/size` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ImageSize {
+    /// compressed size of the tag, summed over platforms for manifest lists
+    pub size: Option<usize>,
+    /// compressed size of each platform, empty unless the tag is a manifest list
+    pub platforms: Vec<PlatformSize>,
+}
+
+/// compressed size of one platform of a manifest list
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct PlatformSize {
+    /// digest of the platform manifest
+    pub digest: String,
+    /// platform of the manifest, `None` for attestations and other artifacts
+    pub platform: Option<ManifestV2ListPlatform>,
+    /// compressed size of the config and layers
+    pub size: Option<usize>,
+}
+
+/// struct to parse registry notification envelopes to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct EventEnvelope {
+    /// events sent by the registry
+    pub events: Vec<RegistryEvent>,
+}
+
+/// struct to parse a registry notification to, also used for `/activity` requests
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RegistryEvent {
+    /// unique id of the event
+    pub id: String,
+    /// date of the event, RFC 3339
+    pub timestamp: String,
+    /// `push`, `pull`, `delete` or `mount`
+    pub action: String,
+    /// manifest or blob the event is about
+    pub target: EventTarget,
+    /// user behind the event
+    pub actor: Option<EventActor>,
+}
+
+/// struct to parse the target of a registry notification to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EventTarget {
+    /// media type of the target
+    pub media_type: Option<String>,
+    /// digest of the target
+    pub digest: Option<String>,
+    /// size of the target in bytes
+    pub size: Option<usize>,
+    /// name of the image
+    pub repository: String,
+    /// tag, only set for tagged manifest events
+    pub tag: Option<String>,
+}
+
+impl EventTarget {
+    /// false when the target is known to be a layer or config blob, deletes only
+    /// carry the repository and digest so a target without media type may be a manifest
+    pub fn is_manifest(&self) -> bool {
+        self.tag.is_some()
+            || self
+                .media_type
+                .as_deref()
+                .is_none_or(|m| m.contains("manifest") || m.contains("image.index"))
+    }
+}
+
+/// struct to parse the actor of a registry notification to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct EventActor {
+    /// name of the user, empty for anonymous access
+    pub name: Option<String>,
+}
+
+/// struct to parse `/refresh_status` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RefreshStatus {
+    /// true while a catalog refresh is running
+    pub running: bool,
+    /// id of the running job, or of the last one
+    pub job: Option<u64>,
+    /// seconds between scheduled refreshes, 0 when only manual refreshes happen
+    pub interval_secs: u64,
+    /// start of the last refresh, unix seconds
+    pub last_started: Option<u64>,
+    /// end of the last finished refresh, unix seconds
+    pub last_finished: Option<u64>,
+    /// duration of the last finished refresh in milliseconds
+    pub last_duration_ms: Option<u64>,
+    /// number of images found by the last successful refresh
+    pub last_count: Option<usize>,
+    /// error of the last refresh, `None` when it succeeded
+    pub last_error: Option<String>,
+}
+
+/// struct to parse `/readyz` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct Readiness {
+    /// true when the storage and every registry are ready
+    pub ready: bool,
+    /// error of the storage backend, `None` when it is reachable
+    pub storage_error: Option<String>,
+    /// readiness of each registry
+    pub registries: Vec<RegistryReadiness>,
+}
+
+/// readiness of one registry
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RegistryReadiness {
+    /// name of the registry
+    pub name: String,
+    /// true when the registry answered recently
+    pub reachable: bool,
+    /// seconds since the registry last answered, `None` when it never did
+    pub last_seen_secs: Option<u64>,
+    /// error of the last background ping while the registry has not answered recently
+    pub error: Option<String>,
+    /// number of images in the catalog, `None` when the storage could not be read
+    pub catalog_size: Option<usize>,
+    /// true when the catalog holds images or was refreshed successfully
+    pub catalog_ready: bool,
+}
+
+/// struct to parse `/config.json` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// address of the api, the same origin as the frontend when empty
+    pub api_base: String,
+}
+
+/// struct to parse `/cache` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct CacheStats {
+    /// tag lists served from the cache or fetched from the registry
+    pub tags: CacheCounts,
+    /// manifests served from the cache or fetched from the registry
+    pub manifests: CacheCounts,
+}
+
+/// hits and misses of one cache since the backend started
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct CacheCounts {
+    /// lookups answered by the cache
+    pub hits: u64,
+    /// lookups that had to ask the registry
+    pub misses: u64,
+}
+
+/// struct to parse `/refresh_catalog` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RefreshJob {
+    /// id of the triggered job, or of the one already running
+    pub job: u64,
+}
+
+/// struct to parse `/retention` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RetentionReport {
+    /// true when nothing was deleted
+    pub dry_run: bool,
+    /// compressed bytes of the removed manifests, layers shared with other images are counted too
+    pub bytes_freed: usize,
+    /// outcome per repository matched by a policy
+    pub repositories: Vec<RepositoryRetention>,
+}
+
+/// retention outcome for a single repository
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RepositoryRetention {
+    /// name of the image
+    pub name: String,
+    /// repository glob of the policy applied
+    pub policy: String,
+    /// tags kept by the policy
+    pub kept: Vec<String>,
+    /// tags removed, or to be removed on a dry run
+    pub deleted: Vec<TagRemoval>,
+    /// failures while inspecting or deleting tags
+    pub errors: Vec<String>,
+}
+
+/// tag removed by a retention policy
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct TagRemoval {
+    /// name of the tag
+    pub tag: String,
+    /// digest of the manifest the tag points to
+    pub digest: String,
+    /// creation date of the image, RFC 3339
+    pub created: Option<String>,
+    /// compressed size of the image
+    pub size: Option<usize>,
+}
+
 /// struct to parse `/tags` requests to
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 pub struct Tags {
     /// name of the image
     pub name: String,
     /// list of tags for specified image
+    #[serde(default, deserialize_with = "null_as_default")]
     pub tags: Vec<String>,
 }
 
+/// the registry answers `null` instead of an empty list for repositories without tags
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// struct to parse `DELETE /manifest` responses to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct DeleteReport {
+    /// digest of the deleted manifest
+    pub digest: String,
+    /// tags that pointed to the digest, all of them are gone with it
+    pub tags: Vec<String>,
+    /// true when the repository had no tag left and was dropped from the catalog
+    pub removed_from_catalog: bool,
+}
+
+/// struct to parse `/compare` requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ImageDiff {
+    /// image the changes are relative to
+    pub base: ComparedImage,
+    /// image compared to `base`
+    pub target: ComparedImage,
+    /// layers present in both images, in the order of `base`
+    pub shared_layers: Vec<ManifestConfig>,
+    /// layers only present in `base`
+    pub base_layers: Vec<ManifestConfig>,
+    /// layers only present in `target`
+    pub target_layers: Vec<ManifestConfig>,
+    /// compressed size of `target` minus the one of `base`, `None` when either is unknown
+    pub size_delta: Option<i64>,
+    /// runtime parameters that differ
+    pub config: Vec<ConfigChange>,
+    /// dockerfile instructions of both images aligned on the ones they share
+    pub history: Vec<StepDiff>,
+    /// platforms only listed by `base`
+    pub base_platforms: Vec<String>,
+    /// platforms only listed by `target`
+    pub target_platforms: Vec<String>,
+}
+
+/// one side of an [`ImageDiff`]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ComparedImage {
+    /// name of the image
+    pub image: String,
+    /// tag or digest as requested
+    pub reference: String,
+    /// digest of the compared manifest, the platform one for manifest lists
+    pub digest: String,
+    /// platform compared, `None` unless the reference is a manifest list
+    pub platform: Option<String>,
+    /// platforms of the manifest list, empty for single images
+    pub platforms: Vec<String>,
+    /// compressed size of the config and layers
+    pub size: Option<usize>,
+    /// creation date of the image, RFC 3339
+    pub created: Option<String>,
+}
+
+/// runtime parameter that differs between two images, `None` on the side lacking it
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ConfigChange {
+    /// `env`, `entrypoint`, `cmd`, `labels`, `exposed_ports` or `user`
+    pub field: String,
+    /// variable, label or port name for the fields holding several values
+    pub key: Option<String>,
+    /// value in the base image
+    pub base: Option<String>,
+    /// value in the target image
+    pub target: Option<String>,
+}
+
+/// build step of either image, `None` on the side lacking it
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct StepDiff {
+    /// instruction of the step in the base image
+    pub base: Option<String>,
+    /// instruction of the step in the target image
+    pub target: Option<String>,
+}
+
 ///enum for Docker manifest version
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DockerManifest {
     ///schema version: 1
     V1(Manifest),
     ///schema version: 2
-    V2(ManifestV2),
+    V2(Box<ManifestV2>),
     ///schema version: 2 + media_type: application/vnd.docker.distribution.manifest.list.v2+json
     V2List(ManifestV2List),
+    ///schema version: 2 + media_type: application/vnd.oci.image.manifest.v1+json
+    OciManifest(Box<OciImageManifest>),
+    ///schema version: 2 + media_type: application/vnd.oci.image.index.v1+json
+    OciIndex(Box<OciImageIndex>),
+}
+
+impl DockerManifest {
+    ///media type declared by the manifest, or the one implied by its schema
+    pub fn media_type(&self) -> &str {
+        match self {
+            V1(_) => MEDIA_TYPE_DOCKER_V1,
+            V2(man) => man.media_type.as_deref().unwrap_or(MEDIA_TYPE_DOCKER_V2),
+            V2List(man) => man.media_type.as_deref().unwrap_or(MEDIA_TYPE_DOCKER_V2_LIST),
+            OciManifest(man) => man.media_type.as_deref().unwrap_or(MEDIA_TYPE_OCI_MANIFEST),
+            OciIndex(man) => man.media_type.as_deref().unwrap_or(MEDIA_TYPE_OCI_INDEX),
+        }
+    }
+
+    ///image config blob, `None` for schema 1 and manifest lists
+    pub fn config(&self) -> Option<&ManifestConfig> {
+        match self {
+            V2(man) => man.config.as_ref(),
+            OciManifest(man) => Some(&man.config),
+            V1(_) | V2List(_) | OciIndex(_) => None,
+        }
+    }
+
+    ///layer blobs, base layer first, empty for schema 1 and manifest lists
+    pub fn layers(&self) -> &[ManifestConfig] {
+        match self {
+            V2(man) => man.layers.as_deref().unwrap_or_default(),
+            OciManifest(man) => &man.layers,
+            V1(_) | V2List(_) | OciIndex(_) => &[],
+        }
+    }
+
+    ///digest of the image config blob, `None` for schema 1 and manifest lists
+    pub fn config_digest(&self) -> Option<&str> {
+        self.config().map(|c| c.digest.as_str())
+    }
+
+    ///compressed size of the config and layers, `None` for schema 1 and manifest lists
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            V2(man) => Some(
+                man.config.iter().chain(man.layers.iter().flatten()).map(|c| c.size).sum(),
+            ),
+            OciManifest(man) => Some(
+                man.config.size + man.layers.iter().map(|c| c.size).sum::<usize>(),
+            ),
+            V1(_) | V2List(_) | OciIndex(_) => None,
+        }
+    }
+
+    ///build steps of the image, oldest first, with the layer each produced
+    ///
+    ///schema 1 manifests carry their history, the others need their image `config`,
+    ///empty for manifest lists
+    pub fn build_steps(
+        &self,
+        config: Option<&ImageConfig>,
+    ) -> Result<Vec<BuildStep>, ManifestError> {
+        let history = match self {
+            V1(man) => {
+                // one history entry per layer, newest first
+                let steps = man
+                    .compatibility()?
+                    .into_iter()
+                    .zip(man.fs_layers.iter())
+                    .rev()
+                    .map(|(step, layer)| BuildStep {
+                        created_by: step
+                            .container_config
+                            .and_then(|c| c.cmd)
+                            .map(|cmd| cmd.join(" ")),
+                        created: step.created,
+                        comment: step.comment,
+                        digest: (!step.throwaway).then(|| layer.blob_sum.clone()),
+                        size: step.size.filter(|_| !step.throwaway),
+                    })
+                    .collect();
+                return Ok(steps);
+            }
+            V2List(_) | OciIndex(_) => return Ok(vec![]),
+            V2(_) | OciManifest(_) => config.and_then(|c| c.history.clone()).unwrap_or_default(),
+        };
+        let mut layers = self.layers().iter();
+        let mut steps: Vec<BuildStep> = history
+            .into_iter()
+            .map(|entry| {
+                let layer = match entry.empty_layer {
+                    true => None,
+                    false => layers.next(),
+                };
+                BuildStep {
+                    created: entry.created,
+                    created_by: entry.created_by,
+                    comment: entry.comment,
+                    digest: layer.map(|l| l.digest.clone()),
+                    size: layer.map(|l| l.size),
+                }
+            })
+            .collect();
+        // layers the history does not account for, e.g. when it was dropped on a squash
+        steps.extend(layers.map(|l| BuildStep {
+            digest: Some(l.digest.clone()),
+            size: Some(l.size),
+            ..Default::default()
+        }));
+        Ok(steps)
+    }
+
+    ///per platform sub manifests, `None` unless this is a manifest list or an OCI index
+    pub fn platforms(&self) -> Option<&[ManifestConfig]> {
+        match self {
+            V2List(man) => man.manifests.as_deref(),
+            OciIndex(man) => Some(man.manifests.as_slice()),
+            V1(_) | V2(_) | OciManifest(_) => None,
+        }
+    }
 }
 
 ///struct for deserializing schema version
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct SchemaVersion {
-    schema_version: usize,
+    schema_version: Option<usize>,
     media_type: Option<String>,
-    errors: Option<ErrorsV2>
+    errors: Option<Vec<ErrorsV2>>,
+    manifests: Option<serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 ///struct for manifest v2 config
 pub struct ManifestV2ListPlatform {
@@ -95,72 +729,441 @@ pub struct ManifestV2ListPlatform {
     pub architecture: String,
     ///contains os
     pub os: String,
-    variant: Option<String>,
-    features: Option<Vec<String>>
+    ///cpu variant, e.g. `v7` for arm
+    pub variant: Option<String>,
+    ///required cpu features
+    pub features: Option<Vec<String>>,
+}
+
+///`os/architecture`, followed by `/variant` when there is one
+impl fmt::Display for ManifestV2ListPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        match &self.variant {
+            Some(variant) => write!(f, "/{}", variant),
+            None => Ok(()),
+        }
+    }
 }
 
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 ///struct for manifest v2 config
 pub struct ManifestConfig {
-    media_type: String,
-    size: usize,
-    digest: String,
+    ///media type of the referenced blob or manifest
+    pub media_type: String,
+    ///compressed size in bytes of the referenced blob or manifest
+    pub size: usize,
+    ///content digest of the referenced blob or manifest
+    pub digest: String,
     /// contains platform specific info
-    pub platform: Option<ManifestV2ListPlatform>
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-#[serde(rename_all = "camelCase")]
-///struct for parsing error details from registry
-pub struct ErrorsV2Detail{
-    tag: Option<String>,
+    pub platform: Option<ManifestV2ListPlatform>,
+    /// OCI arbitrary metadata
+    pub annotations: Option<HashMap<String, String>>,
+    /// OCI type of the referenced artifact
+    pub artifact_type: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 ///struct for parsing errors from registry
 pub struct ErrorsV2{
-    code: String,
-    message: String,
-    detail: ErrorsV2Detail,
+    ///error code, e.g. `MANIFEST_UNKNOWN`, `NAME_UNKNOWN` or `UNAUTHORIZED`
+    pub code: String,
+    ///human readable message
+    pub message: String,
+    ///unstructured details, shape depends on the error code
+    pub detail: Option<serde_json::Value>,
 }
 
 /// struct to parse `fsLayers` v1 to
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct LayerV1{
-    blob_sum: String
+    ///digest of the layer blob
+    pub blob_sum: String
+}
+
+/// struct to parse `history` v1 entries to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryV1{
+    ///json of a [`V1Compatibility`], kept as sent so that signatures still match
+    pub v1_compatibility: String
+}
+
+/// struct to parse the `v1Compatibility` json of a v1 history entry to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct V1Compatibility{
+    ///id of the layer
+    pub id: Option<String>,
+    ///id of the parent layer
+    pub parent: Option<String>,
+    ///creation date of the step, RFC 3339
+    pub created: Option<String>,
+    ///author of the step
+    pub author: Option<String>,
+    ///custom message
+    pub comment: Option<String>,
+    ///configuration of the container the step ran in, `Cmd` is the command of the step
+    pub container_config: Option<ContainerConfig>,
+    ///uncompressed size of the layer
+    #[serde(rename = "Size")]
+    pub size: Option<usize>,
+    ///true when the step did not produce a layer
+    #[serde(default)]
+    pub throwaway: bool,
 }
 
 /// struct to parse `/manifest` v1 requests to
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Manifest{
     name: String,
     tag: String,
     ///cpu architecture
     pub architecture: String,
-    //fs_layers: Vec<LayerV1>,
-    //history: Vec<String>
+    ///layers, newest first
+    #[serde(default)]
+    pub fs_layers: Vec<LayerV1>,
+    ///build steps, newest first, one per layer
+    #[serde(default)]
+    pub history: Vec<HistoryV1>,
+}
+
+impl Manifest {
+    ///parsed `v1Compatibility` of the history entries, newest first
+    pub fn compatibility(&self) -> Result<Vec<V1Compatibility>, ManifestError> {
+        Ok(self
+            .history
+            .iter()
+            .map(|h| serde_json::from_str(&h.v1_compatibility))
+            .collect::<Result<_, _>>()?)
+    }
 }
 
 /// struct to parse `/manifest` v2 requests to
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ManifestV2{
     media_type: Option<String>,
     ///list of sub manifests
     pub manifests: Option<Vec<ManifestConfig>>,
+    config: Option<ManifestConfig>,
     layers: Option<Vec<ManifestConfig>>,
 }
 
 /// struct to parse `/manifest` v2 requests to
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ManifestV2List{
     media_type: Option<String>,
     ///list of sub manifests
     pub manifests: Option<Vec<ManifestConfig>>,
-}
\ No newline at end of file
+}
+
+/// struct to parse `/manifest` OCI image manifest requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OciImageManifest{
+    media_type: Option<String>,
+    ///type of the artifact when the manifest is not an image
+    pub artifact_type: Option<String>,
+    config: ManifestConfig,
+    layers: Vec<ManifestConfig>,
+    ///manifest this one refers to
+    pub subject: Option<ManifestConfig>,
+    ///arbitrary metadata
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+/// struct to parse `/manifest` OCI image index requests to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OciImageIndex{
+    media_type: Option<String>,
+    ///type of the artifact when the index is not an image
+    pub artifact_type: Option<String>,
+    ///list of sub manifests
+    pub manifests: Vec<ManifestConfig>,
+    ///manifest this one refers to
+    pub subject: Option<ManifestConfig>,
+    ///arbitrary metadata
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+/// struct to parse image config blobs (`/blobs/{digest}`) to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ImageConfig{
+    ///creation date, RFC 3339
+    pub created: Option<String>,
+    ///author of the image
+    pub author: Option<String>,
+    ///cpu architecture
+    pub architecture: String,
+    ///operating system
+    pub os: String,
+    ///cpu variant
+    pub variant: Option<String>,
+    ///runtime parameters of the image
+    pub config: Option<ContainerConfig>,
+    ///layer content addresses
+    pub rootfs: Option<RootFs>,
+    ///build steps of the image, oldest first
+    pub history: Option<Vec<HistoryEntry>>,
+}
+
+/// struct to parse the `config` section of an image config to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig{
+    ///user the process runs as
+    pub user: Option<String>,
+    ///exposed ports, e.g. `8080/tcp`
+    pub exposed_ports: Option<HashMap<String, serde_json::Value>>,
+    ///environment variables as `KEY=value`
+    pub env: Option<Vec<String>>,
+    ///entrypoint arguments
+    pub entrypoint: Option<Vec<String>>,
+    ///default arguments to the entrypoint
+    pub cmd: Option<Vec<String>>,
+    ///volume mount points
+    pub volumes: Option<HashMap<String, serde_json::Value>>,
+    ///working directory of the process
+    pub working_dir: Option<String>,
+    ///arbitrary metadata
+    pub labels: Option<HashMap<String, String>>,
+    ///signal sent to stop the container
+    pub stop_signal: Option<String>,
+}
+
+/// struct to parse the `rootfs` section of an image config to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RootFs{
+    ///always `layers`
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    ///uncompressed layer digests, in order
+    pub diff_ids: Vec<String>,
+}
+
+/// struct to parse `history` entries of an image config to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct HistoryEntry{
+    ///creation date of the step, RFC 3339
+    pub created: Option<String>,
+    ///command that created the step
+    pub created_by: Option<String>,
+    ///author of the step
+    pub author: Option<String>,
+    ///custom message
+    pub comment: Option<String>,
+    ///true when the step did not produce a layer
+    #[serde(default)]
+    pub empty_layer: bool,
+}
+
+/// step of an image build with the layer it produced, see [`DockerManifest::build_steps`]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct BuildStep{
+    ///creation date of the step, RFC 3339
+    pub created: Option<String>,
+    ///command that created the step, as recorded in the history
+    pub created_by: Option<String>,
+    ///custom message
+    pub comment: Option<String>,
+    ///digest of the produced layer, `None` for steps that only changed metadata
+    pub digest: Option<String>,
+    ///size of the produced layer, compressed except for schema 1 where it is uncompressed
+    pub size: Option<usize>,
+}
+
+impl BuildStep {
+    ///dockerfile instruction of the step, reconstructed from its command
+    pub fn instruction(&self) -> String {
+        const SHELL: &str = "/bin/sh -c ";
+        let created_by = self.created_by.as_deref().unwrap_or_default().trim();
+        // buildkit records the instruction itself, marked with a trailing comment
+        let created_by = created_by.strip_suffix("# buildkit").unwrap_or(created_by).trim_end();
+        // the shell command of `RUN` comes after its build arguments, `|2 A=1 B=2`
+        let args = created_by.starts_with('|') || created_by.starts_with("RUN ");
+        let shell = match created_by.find(SHELL) {
+            Some(start) if start == 0 || args => Some(&created_by[start + SHELL.len()..]),
+            _ => None,
+        };
+        match shell {
+            // the classic builder runs other instructions as a no-op shell command
+            Some(command) => match command.strip_prefix("#(nop)") {
+                Some(instruction) => instruction.trim().to_string(),
+                None => format!("RUN {}", command.trim()),
+            },
+            None if created_by.is_empty() => "# no command recorded".to_string(),
+            None => created_by.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn step(created_by: &str) -> BuildStep {
+        BuildStep {
+            created_by: Some(created_by.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// steps as `(instruction, digest, size)`
+    fn summary(steps: &[BuildStep]) -> Vec<(String, Option<&str>, Option<usize>)> {
+        steps.iter().map(|s| (s.instruction(), s.digest.as_deref(), s.size)).collect()
+    }
+
+    fn config(history: &[(&str, bool)]) -> ImageConfig {
+        ImageConfig {
+            history: Some(
+                history
+                    .iter()
+                    .map(|(created_by, empty_layer)| HistoryEntry {
+                        created_by: Some(created_by.to_string()),
+                        empty_layer: *empty_layer,
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn strips_nop_commands() {
+        assert_eq!(
+            step(r#"/bin/sh -c #(nop)  CMD ["nginx" "-g" "daemon off;"]"#).instruction(),
+            r#"CMD ["nginx" "-g" "daemon off;"]"#
+        );
+        assert_eq!(
+            step("/bin/sh -c #(nop) ADD file:abc in / ").instruction(),
+            "ADD file:abc in /"
+        );
+        assert_eq!(
+            step("/bin/sh -c apt-get update && apt-get install -y curl").instruction(),
+            "RUN apt-get update && apt-get install -y curl"
+        );
+    }
+
+    #[test]
+    fn strips_build_argument_prefixes() {
+        assert_eq!(
+            step("|2 VERSION=1.2 TARGET=prod /bin/sh -c make install").instruction(),
+            "RUN make install"
+        );
+        assert_eq!(
+            step("|1 A=x /bin/sh -c #(nop) ENV B=x").instruction(),
+            "ENV B=x"
+        );
+        // a shell quoted inside another instruction is left alone
+        assert_eq!(
+            step("ENTRYPOINT [\"/bin/sh -c \"]").instruction(),
+            "ENTRYPOINT [\"/bin/sh -c \"]"
+        );
+    }
+
+    #[test]
+    fn strips_buildkit_suffixes() {
+        assert_eq!(
+            step("RUN /bin/sh -c go build ./... # buildkit").instruction(),
+            "RUN go build ./..."
+        );
+        assert_eq!(step("COPY . /src # buildkit").instruction(), "COPY . /src");
+        assert_eq!(step("WORKDIR /src").instruction(), "WORKDIR /src");
+        assert_eq!(BuildStep::default().instruction(), "# no command recorded");
+        assert_eq!(step("  ").instruction(), "# no command recorded");
+    }
+
+    #[test]
+    fn orders_schema_1_history() {
+        let compatibility = |cmd: &str, size: usize, throwaway: bool| {
+            json!({
+                "v1Compatibility": json!({
+                    "container_config": {"Cmd": ["/bin/sh", "-c", cmd]},
+                    "Size": size,
+                    "throwaway": throwaway,
+                })
+                .to_string()
+            })
+        };
+        let manifest = get_manifest(
+            &json!({
+                "schemaVersion": 1,
+                "name": "app",
+                "tag": "1",
+                "architecture": "amd64",
+                "fsLayers": [
+                    {"blobSum": "sha256:c"},
+                    {"blobSum": "sha256:b"},
+                    {"blobSum": "sha256:a"},
+                ],
+                "history": [
+                    compatibility("#(nop) CMD [\"sh\"]", 0, true),
+                    compatibility("echo b > b", 20, false),
+                    compatibility("#(nop) ADD file:a in /", 10, false),
+                ],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            summary(&manifest.build_steps(None).unwrap()),
+            vec![
+                ("ADD file:a in /".to_string(), Some("sha256:a"), Some(10)),
+                ("RUN echo b > b".to_string(), Some("sha256:b"), Some(20)),
+                ("CMD [\"sh\"]".to_string(), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_empty_layers_to_steps() {
+        for media_type in [MEDIA_TYPE_DOCKER_V2, MEDIA_TYPE_OCI_MANIFEST] {
+            let manifest = get_manifest(
+                &json!({
+                    "schemaVersion": 2,
+                    "mediaType": media_type,
+                    "config": {"mediaType": "config", "digest": "sha256:conf", "size": 1},
+                    "layers": [
+                        {"mediaType": "layer", "digest": "sha256:a", "size": 10},
+                        {"mediaType": "layer", "digest": "sha256:b", "size": 20},
+                        {"mediaType": "layer", "digest": "sha256:squashed", "size": 30},
+                    ],
+                })
+                .to_string(),
+            )
+            .unwrap();
+            let config = config(&[
+                ("/bin/sh -c #(nop) ADD file:a in /", false),
+                ("/bin/sh -c #(nop) ENV A=1", true),
+                ("RUN /bin/sh -c echo b > b # buildkit", false),
+            ]);
+            assert_eq!(
+                summary(&manifest.build_steps(Some(&config)).unwrap()),
+                vec![
+                    ("ADD file:a in /".to_string(), Some("sha256:a"), Some(10)),
+                    ("ENV A=1".to_string(), None, None),
+                    ("RUN echo b > b".to_string(), Some("sha256:b"), Some(20)),
+                    // layers the history does not account for
+                    (
+                        "# no command recorded".to_string(),
+                        Some("sha256:squashed"),
+                        Some(30)
+                    ),
+                ],
+                "{}",
+                media_type
+            );
+            // without a config only the layers are known
+            assert_eq!(manifest.build_steps(None).unwrap().len(), 3);
+        }
+    }
+}